@@ -82,7 +82,7 @@ fn main() {
     while running {
         for event in event_mapper.process(&poll_events(&mut events_loop)) {
             match event {
-                Event::Close | Event::Controller(Action::Close, ..) => {
+                Event::Close(_) | Event::Controller(Action::Close, ..) => {
                     println!("closing!");
                     running = false;
                 }
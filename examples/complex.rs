@@ -157,23 +157,13 @@ fn main() {
         .activate_context(&ContextId::Default, 1);
 
     let mut running = true;
-    let mut ui_active = false;
     while running {
         for event in event_mapper.process(&poll_events(&mut events_loop)) {
             match event {
-                Event::Close | Event::Controller(ControllerAction::UI(UIAction::Close), ..) => {
+                Event::Close(_) | Event::Controller(ControllerAction::UI(UIAction::Close), ..) => {
                     println!("closing!");
                     running = false;
                 }
-                Event::Controller(ControllerAction::Game(GameAction::ToggleUI), ..) => {
-                    if ui_active {
-                        ui_active = false;
-                        event_mapper.deactivate_context(&ContextId::UI);
-                    } else {
-                        ui_active = true;
-                        event_mapper.activate_context(&ContextId::UI, 2);
-                    }
-                }
                 Event::Controller(x, ..) => {
                     println!("controller event: {:?}", x);
                 }
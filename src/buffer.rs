@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use std::clone::Clone;
+use std::cmp::Eq;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use event::{ActionType, Event, StateAction, Timestamp};
+
+pub const DEFAULT_BUFFER_WINDOW: f64 = 0.1;
+
+#[derive(Debug)]
+pub struct InputBuffer<ACTION>
+where
+    ACTION: Hash + Eq + Clone,
+{
+    window: f64,
+    windows: HashMap<ACTION, f64>,
+    entries: Vec<(ACTION, Timestamp)>,
+}
+
+impl<ACTION> InputBuffer<ACTION>
+where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    pub fn new(window: f64) -> InputBuffer<ACTION> {
+        InputBuffer {
+            window,
+            windows: HashMap::default(),
+            entries: Vec::default(),
+        }
+    }
+
+    pub fn set_window(&mut self, window: f64) {
+        self.window = window;
+    }
+
+    pub fn set_action_window(&mut self, action: ACTION, window: f64) {
+        self.windows.insert(action, window);
+    }
+
+    pub fn window(&self, action: &ACTION) -> f64 {
+        self.windows.get(action).cloned().unwrap_or(self.window)
+    }
+
+    pub fn update<ID>(&mut self, events: &Vec<Event<ACTION, ID>>, now: Timestamp)
+    where
+        ID: Clone + Debug,
+    {
+        for e in events {
+            match *e {
+                Event::Controller(ref action, ActionType::Action, _, time) |
+                Event::Controller(
+                    ref action,
+                    ActionType::State(StateAction::Activated, _),
+                    _,
+                    time,
+                ) => self.entries.push((action.clone(), time)),
+                _ => (),
+            }
+        }
+        self.expire(now);
+    }
+
+    pub fn is_buffered(&self, action: &ACTION, now: Timestamp) -> bool {
+        let window = self.window(action);
+        self.entries
+            .iter()
+            .any(|&(ref a, time)| a == action && now - time <= window)
+    }
+
+    pub fn consume(&mut self, action: &ACTION, now: Timestamp) -> bool {
+        self.expire(now);
+        match self.entries.iter().position(|&(ref a, _)| a == action) {
+            Some(index) => {
+                self.entries.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, action: &ACTION) {
+        self.entries.retain(|&(ref a, _)| a != action);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn expire(&mut self, now: Timestamp) {
+        let (window, windows) = (self.window, &self.windows);
+        self.entries.retain(|&(ref action, time)| {
+            now - time <= windows.get(action).cloned().unwrap_or(window)
+        });
+    }
+}
@@ -0,0 +1,54 @@
+use time;
+
+use std::sync::{Arc, Mutex};
+
+pub trait Clock: Send {
+    fn now(&self) -> f64;
+
+    fn advance(&self, _dt: f64) {}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        time::precise_time_ns() as f64 / 1000000000.0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    time: Arc<Mutex<f64>>,
+}
+
+impl ManualClock {
+    pub fn new(time: f64) -> ManualClock {
+        ManualClock {
+            time: Arc::new(Mutex::new(time)),
+        }
+    }
+
+    pub fn set(&self, time: f64) {
+        *self.time.lock().unwrap() = time;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 {
+        *self.time.lock().unwrap()
+    }
+
+    fn advance(&self, dt: f64) {
+        *self.time.lock().unwrap() += dt.max(0.0);
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> f64 + Send,
+{
+    fn now(&self) -> f64 {
+        self()
+    }
+}
@@ -7,7 +7,16 @@ pub enum FocusAction {
     Exit,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextAction {
+    Activated,
+    Deactivated,
+    Succeeded,
+    TimedOut,
+}
+
 pub type StateDuration = f64;
+pub type Timestamp = f64;
 pub type RangeDiff = (f64, f64);
 
 #[derive(Debug, Clone, PartialEq)]
@@ -34,12 +43,26 @@ pub enum ActionType {
     Action,
     State(StateAction, StateDuration),
     Range(RangeDiff),
+    Blocked(f64),
 }
 
 #[derive(Debug, Clone)]
 pub enum Event<ACTION: Debug, ID: Debug + Clone> {
-    Controller(ACTION, ActionType, Vec<Argument<ID>>),
-    Resize(u32, u32),
-    Focus(FocusAction),
-    Close,
+    Controller(ACTION, ActionType, Vec<Argument<ID>>, Timestamp),
+    Resize(u32, u32, Timestamp),
+    Focus(FocusAction, Timestamp),
+    Context(ContextAction, ID, Timestamp),
+    Close(Timestamp),
+}
+
+impl<ACTION: Debug, ID: Debug + Clone> Event<ACTION, ID> {
+    pub fn timestamp(&self) -> Timestamp {
+        match *self {
+            Event::Controller(_, _, _, timestamp) |
+            Event::Resize(_, _, timestamp) |
+            Event::Focus(_, timestamp) |
+            Event::Context(_, _, timestamp) |
+            Event::Close(timestamp) => timestamp,
+        }
+    }
 }
@@ -0,0 +1,39 @@
+use event::Timestamp;
+use types::{MappingHandle, RawState, RawType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchResult {
+    Matched,
+    Unmapped,
+    Masked,
+    RepeatSuppressed,
+    ConditionFailed,
+    RawTypeMismatch,
+    StateMismatch,
+    ThresholdNotCrossed,
+    ToggleSkipped,
+    Limited,
+    HoldPending,
+}
+
+#[derive(Debug, Clone)]
+pub struct MappingTrace {
+    pub handle: MappingHandle,
+    pub result: MatchResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextTrace<ID> {
+    pub context_id: ID,
+    pub mappings: Vec<MappingTrace>,
+}
+
+// Text input is only ever reported as RawType::Char, never with the typed character.
+#[derive(Debug, Clone)]
+pub struct InputTrace<ID> {
+    pub input: Option<RawType>,
+    pub state: RawState,
+    pub time: Timestamp,
+    pub contexts: Vec<ContextTrace<ID>>,
+    pub winner: Option<(ID, MappingHandle)>,
+}
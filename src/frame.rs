@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use std::clone::Clone;
+use std::cmp::Eq;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use event::{ActionType, Event, RangeDiff, StateAction};
+
+#[derive(Debug, Clone, Default)]
+struct ActionFrame {
+    down: bool,
+    just_pressed: bool,
+    just_released: bool,
+    pressed_at: f64,
+    range: RangeDiff,
+}
+
+#[derive(Debug)]
+pub struct InputFrame<ACTION>
+where
+    ACTION: Hash + Eq + Clone,
+{
+    actions: HashMap<ACTION, ActionFrame>,
+    time: f64,
+}
+
+impl<ACTION> InputFrame<ACTION>
+where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    pub fn new() -> InputFrame<ACTION> {
+        InputFrame {
+            actions: HashMap::default(),
+            time: 0.0,
+        }
+    }
+
+    pub fn begin(&mut self) {
+        for frame in self.actions.values_mut() {
+            frame.just_pressed = false;
+            frame.just_released = false;
+            frame.range = (0.0, 0.0);
+        }
+    }
+
+    pub fn update<ID>(&mut self, events: &Vec<Event<ACTION, ID>>, time: f64)
+    where
+        ID: Clone + Debug,
+    {
+        self.time = time;
+        for e in events {
+            if let Event::Controller(ref action, ref action_type, ..) = *e {
+                let frame = self.actions
+                    .entry(action.clone())
+                    .or_insert_with(ActionFrame::default);
+                match *action_type {
+                    ActionType::Action => frame.just_pressed = true,
+                    ActionType::State(StateAction::Activated, _) => {
+                        if !frame.down {
+                            frame.down = true;
+                            frame.just_pressed = true;
+                            frame.pressed_at = time;
+                        }
+                    }
+                    ActionType::State(StateAction::Active, _) => if !frame.down {
+                        frame.down = true;
+                        frame.pressed_at = time;
+                    },
+                    ActionType::State(StateAction::Deactivated, _) => if frame.down {
+                        frame.down = false;
+                        frame.just_released = true;
+                    },
+                    ActionType::Range((x, y)) => {
+                        frame.range = (frame.range.0 + x, frame.range.1 + y);
+                    }
+                    ActionType::Blocked(_) => (),
+                }
+            }
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn is_down(&self, action: &ACTION) -> bool {
+        self.actions.get(action).map(|f| f.down).unwrap_or(false)
+    }
+
+    pub fn just_pressed(&self, action: &ACTION) -> bool {
+        self.actions
+            .get(action)
+            .map(|f| f.just_pressed)
+            .unwrap_or(false)
+    }
+
+    pub fn just_released(&self, action: &ACTION) -> bool {
+        self.actions
+            .get(action)
+            .map(|f| f.just_released)
+            .unwrap_or(false)
+    }
+
+    pub fn held_duration(&self, action: &ACTION) -> f64 {
+        match self.actions.get(action) {
+            Some(f) if f.down => self.time - f.pressed_at,
+            _ => 0.0,
+        }
+    }
+
+    pub fn range(&self, action: &ACTION) -> RangeDiff {
+        self.actions
+            .get(action)
+            .map(|f| f.range)
+            .unwrap_or((0.0, 0.0))
+    }
+}
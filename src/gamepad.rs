@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::str;
+
+use winit;
+
+use types::{GamepadAxis, GamepadButton, RawState};
+
+const BUTTON_AXIS_THRESHOLD: f64 = 0.5;
+
+#[derive(Debug)]
+pub enum GamepadDbError {
+    FileNotFound,
+    ReadFailed,
+    Utf8Error,
+    InvalidGuid,
+    MissingName,
+    InvalidBinding(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AxisRange {
+    Full,
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceInput {
+    Button(u32),
+    Axis(u32, AxisRange, bool),
+    Hat(u32, u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadInput {
+    Button(GamepadButton),
+    Axis(GamepadAxis, AxisRange),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadEvent {
+    Button(GamepadButton, RawState),
+    Axis(GamepadAxis, f64),
+}
+
+// Last digital state of each binding driven by an axis or hat, by binding index.
+#[derive(Debug, Clone, Default)]
+pub struct GamepadState {
+    digital: HashMap<usize, bool>,
+}
+
+impl GamepadState {
+    pub fn new() -> GamepadState {
+        GamepadState::default()
+    }
+
+    fn changed(&mut self, binding: usize, event: GamepadEvent) -> Option<GamepadEvent> {
+        if let GamepadEvent::Button(_, ref state) = event {
+            let pressed = *state == RawState::Press;
+            if self.digital.insert(binding, pressed).unwrap_or(false) == pressed {
+                return None;
+            }
+        }
+        Some(event)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GamepadLayout {
+    pub guid: String,
+    pub name: String,
+    pub platform: Option<String>,
+    pub bindings: Vec<(DeviceInput, GamepadInput)>,
+}
+
+impl GamepadLayout {
+    pub fn parse(line: &str) -> Result<GamepadLayout, GamepadDbError> {
+        let mut parts = line.trim().split(',');
+        let guid = parts
+            .next()
+            .map(|g| g.trim().to_lowercase())
+            .ok_or(GamepadDbError::InvalidGuid)?;
+        if guid.len() != 32 || !guid.chars().all(|c| c.is_digit(16)) {
+            return Err(GamepadDbError::InvalidGuid);
+        }
+        let name = match parts.next() {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => return Err(GamepadDbError::MissingName),
+        };
+        let mut layout = GamepadLayout {
+            guid,
+            name,
+            platform: None,
+            bindings: Vec::default(),
+        };
+        for part in parts.map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let mut kv = part.splitn(2, ':');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next()
+                .ok_or_else(|| GamepadDbError::InvalidBinding(part.to_string()))?;
+            match key {
+                "platform" => layout.platform = Some(value.to_string()),
+                "crc" | "hint" | "sdk>=" | "sdk<=" => (),
+                _ => layout.bindings.push((
+                    parse_device_input(value)
+                        .ok_or_else(|| GamepadDbError::InvalidBinding(part.to_string()))?,
+                    parse_gamepad_input(key)
+                        .ok_or_else(|| GamepadDbError::InvalidBinding(part.to_string()))?,
+                )),
+            }
+        }
+        Ok(layout)
+    }
+
+    pub fn find(&self, input: &GamepadInput) -> Option<&DeviceInput> {
+        self.bindings
+            .iter()
+            .find(|&&(_, ref i)| i == input)
+            .map(|&(ref d, _)| d)
+    }
+
+    pub fn translate_button(&self, index: u32, state: &RawState) -> Vec<GamepadEvent> {
+        let pressed = *state == RawState::Press;
+        self.bindings
+            .iter()
+            .filter(|&&(ref d, _)| *d == DeviceInput::Button(index))
+            .map(|&(_, ref i)| digital_event(i, pressed))
+            .collect()
+    }
+
+    pub fn translate_axis(
+        &self,
+        index: u32,
+        value: f64,
+        state: &mut GamepadState,
+    ) -> Vec<GamepadEvent> {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(n, &(ref d, ref i))| match *d {
+                DeviceInput::Axis(a, ref range, inverted) if a == index => {
+                    let event = axis_event(i, range, if inverted { -value } else { value });
+                    state.changed(n, event)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn translate_hat(
+        &self,
+        index: u32,
+        mask: u8,
+        state: &mut GamepadState,
+    ) -> Vec<GamepadEvent> {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(n, &(ref d, ref i))| match *d {
+                DeviceInput::Hat(h, bit) if h == index => {
+                    state.changed(n, digital_event(i, mask & bit != 0))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn translate(
+        &self,
+        raw_input: &winit::DeviceEvent,
+        state: &mut GamepadState,
+    ) -> Vec<GamepadEvent> {
+        match *raw_input {
+            winit::DeviceEvent::Button { button, state } => {
+                self.translate_button(button, &state.into())
+            }
+            winit::DeviceEvent::Motion { axis, value } => self.translate_axis(axis, value, state),
+            _ => Vec::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GamepadDatabase {
+    layouts: HashMap<String, GamepadLayout>,
+}
+
+impl GamepadDatabase {
+    pub fn new() -> GamepadDatabase {
+        GamepadDatabase::default()
+    }
+
+    pub fn get(&self, guid: &str) -> Option<&GamepadLayout> {
+        self.layouts.get(&guid.to_lowercase())
+    }
+
+    pub fn with_layout(&mut self, layout: GamepadLayout) -> &mut Self {
+        self.layouts.insert(layout.guid.clone(), layout);
+        self
+    }
+
+    pub fn with_mappings(&mut self, data: &str) -> &mut Self {
+        let platform = current_platform();
+        for line in data.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            match GamepadLayout::parse(line) {
+                Ok(layout) => if layout
+                    .platform
+                    .as_ref()
+                    .map(|p| p == platform)
+                    .unwrap_or(true)
+                {
+                    self.with_layout(layout);
+                },
+                Err(e) => warn!("Skipping gamepad mapping {:?}: {:?}", line, e),
+            }
+        }
+        self
+    }
+}
+
+pub fn database_from_file(file: &str) -> Result<GamepadDatabase, GamepadDbError> {
+    database_from_reader(File::open(file).map_err(|_| GamepadDbError::FileNotFound)?)
+}
+
+pub fn database_from_reader<R>(mut rdr: R) -> Result<GamepadDatabase, GamepadDbError>
+where
+    R: Read,
+{
+    let mut bytes = Vec::new();
+    rdr.read_to_end(&mut bytes)
+        .map_err(|_| GamepadDbError::ReadFailed)?;
+    Ok(database_from_str(str::from_utf8(&bytes)
+        .map_err(|_| GamepadDbError::Utf8Error)?))
+}
+
+pub fn database_from_str(data: &str) -> GamepadDatabase {
+    let mut database = GamepadDatabase::new();
+    database.with_mappings(data);
+    database
+}
+
+fn current_platform() -> &'static str {
+    match env::consts::OS {
+        "windows" => "Windows",
+        "macos" => "Mac OS X",
+        "linux" => "Linux",
+        "android" => "Android",
+        "ios" => "iOS",
+        other => other,
+    }
+}
+
+fn digital_event(input: &GamepadInput, pressed: bool) -> GamepadEvent {
+    match *input {
+        GamepadInput::Button(ref button) => GamepadEvent::Button(
+            button.clone(),
+            if pressed {
+                RawState::Press
+            } else {
+                RawState::Release
+            },
+        ),
+        GamepadInput::Axis(ref axis, ref range) => GamepadEvent::Axis(
+            axis.clone(),
+            match (pressed, range) {
+                (false, _) => 0.0,
+                (true, &AxisRange::Negative) => -1.0,
+                (true, _) => 1.0,
+            },
+        ),
+    }
+}
+
+fn axis_event(input: &GamepadInput, input_range: &AxisRange, value: f64) -> GamepadEvent {
+    let half = match *input_range {
+        AxisRange::Full => None,
+        AxisRange::Positive => Some(value.max(0.0)),
+        AxisRange::Negative => Some((-value).max(0.0)),
+    };
+    match *input {
+        GamepadInput::Button(_) => digital_event(
+            input,
+            half.unwrap_or(value.abs()) > BUTTON_AXIS_THRESHOLD,
+        ),
+        GamepadInput::Axis(ref axis, ref output_range) => GamepadEvent::Axis(
+            axis.clone(),
+            match (output_range, half) {
+                (&AxisRange::Positive, h) => h.unwrap_or(value.max(0.0)),
+                (&AxisRange::Negative, h) => -h.unwrap_or((-value).max(0.0)),
+                (&AxisRange::Full, Some(h)) => if is_trigger(axis) {
+                    h
+                } else {
+                    h * 2.0 - 1.0
+                },
+                (&AxisRange::Full, None) => if is_trigger(axis) {
+                    (value + 1.0) / 2.0
+                } else {
+                    value
+                },
+            },
+        ),
+    }
+}
+
+fn is_trigger(axis: &GamepadAxis) -> bool {
+    match *axis {
+        GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => true,
+        _ => false,
+    }
+}
+
+fn parse_device_input(value: &str) -> Option<DeviceInput> {
+    let (range, rest) = if value.starts_with('+') {
+        (AxisRange::Positive, &value[1..])
+    } else if value.starts_with('-') {
+        (AxisRange::Negative, &value[1..])
+    } else {
+        (AxisRange::Full, value)
+    };
+    let (inverted, rest) = if rest.ends_with('~') {
+        (true, &rest[..rest.len() - 1])
+    } else {
+        (false, rest)
+    };
+    if rest.starts_with('a') {
+        rest[1..]
+            .parse()
+            .ok()
+            .map(|index| DeviceInput::Axis(index, range, inverted))
+    } else if range != AxisRange::Full || inverted {
+        None
+    } else if rest.starts_with('b') {
+        rest[1..].parse().ok().map(DeviceInput::Button)
+    } else if rest.starts_with('h') {
+        let mut hat = rest[1..].splitn(2, '.');
+        match (
+            hat.next().and_then(|h| h.parse().ok()),
+            hat.next().and_then(|m| m.parse().ok()),
+        ) {
+            (Some(index), Some(mask)) => Some(DeviceInput::Hat(index, mask)),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn parse_gamepad_input(key: &str) -> Option<GamepadInput> {
+    let (range, name) = if key.starts_with('+') {
+        (AxisRange::Positive, &key[1..])
+    } else if key.starts_with('-') {
+        (AxisRange::Negative, &key[1..])
+    } else {
+        (AxisRange::Full, key)
+    };
+    let axis = match name {
+        "leftx" => Some(GamepadAxis::LeftX),
+        "lefty" => Some(GamepadAxis::LeftY),
+        "rightx" => Some(GamepadAxis::RightX),
+        "righty" => Some(GamepadAxis::RightY),
+        "lefttrigger" => Some(GamepadAxis::LeftTrigger),
+        "righttrigger" => Some(GamepadAxis::RightTrigger),
+        _ => None,
+    };
+    if let Some(axis) = axis {
+        return Some(GamepadInput::Axis(axis, range));
+    }
+    if range != AxisRange::Full {
+        return None;
+    }
+    match name {
+        "a" => Some(GamepadButton::South),
+        "b" => Some(GamepadButton::East),
+        "x" => Some(GamepadButton::West),
+        "y" => Some(GamepadButton::North),
+        "back" => Some(GamepadButton::Back),
+        "guide" => Some(GamepadButton::Guide),
+        "start" => Some(GamepadButton::Start),
+        "leftstick" => Some(GamepadButton::LeftStick),
+        "rightstick" => Some(GamepadButton::RightStick),
+        "leftshoulder" => Some(GamepadButton::LeftShoulder),
+        "rightshoulder" => Some(GamepadButton::RightShoulder),
+        "dpup" => Some(GamepadButton::DPadUp),
+        "dpdown" => Some(GamepadButton::DPadDown),
+        "dpleft" => Some(GamepadButton::DPadLeft),
+        "dpright" => Some(GamepadButton::DPadRight),
+        "misc1" => Some(GamepadButton::Misc1),
+        "paddle1" => Some(GamepadButton::Paddle1),
+        "paddle2" => Some(GamepadButton::Paddle2),
+        "paddle3" => Some(GamepadButton::Paddle3),
+        "paddle4" => Some(GamepadButton::Paddle4),
+        "touchpad" => Some(GamepadButton::Touchpad),
+        _ => None,
+    }.map(GamepadInput::Button)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XBOX: &str = "030000005e0400008e02000014010000,Xbox 360 Controller,a:b0,b:b1,x:b2,\
+                        y:b3,leftx:a0,lefty:a1,lefttrigger:a2,righttrigger:a5,-rightx:a3,\
+                        +rightx:a4~,dpup:h0.1,platform:Linux,";
+
+    #[test]
+    fn parses_guid_name_and_platform() {
+        let layout = GamepadLayout::parse(XBOX).unwrap();
+        assert_eq!(layout.guid, "030000005e0400008e02000014010000");
+        assert_eq!(layout.name, "Xbox 360 Controller");
+        assert_eq!(layout.platform, Some("Linux".to_string()));
+        assert_eq!(layout.bindings.len(), 11);
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let layout = GamepadLayout::parse(XBOX).unwrap();
+        let find = |input| layout.find(&input).cloned();
+        assert_eq!(
+            find(GamepadInput::Button(GamepadButton::South)),
+            Some(DeviceInput::Button(0))
+        );
+        assert_eq!(
+            find(GamepadInput::Axis(GamepadAxis::LeftX, AxisRange::Full)),
+            Some(DeviceInput::Axis(0, AxisRange::Full, false))
+        );
+        assert_eq!(
+            find(GamepadInput::Axis(GamepadAxis::RightX, AxisRange::Negative)),
+            Some(DeviceInput::Axis(3, AxisRange::Full, false))
+        );
+        assert_eq!(
+            find(GamepadInput::Axis(GamepadAxis::RightX, AxisRange::Positive)),
+            Some(DeviceInput::Axis(4, AxisRange::Full, true))
+        );
+        assert_eq!(
+            find(GamepadInput::Button(GamepadButton::DPadUp)),
+            Some(DeviceInput::Hat(0, 1))
+        );
+    }
+
+    #[test]
+    fn lowercases_guid() {
+        let layout = GamepadLayout::parse("030000005E0400008E02000014010000,Pad,a:b0").unwrap();
+        assert_eq!(layout.guid, "030000005e0400008e02000014010000");
+    }
+
+    #[test]
+    fn rejects_invalid_guid() {
+        match GamepadLayout::parse("xyz,Pad,a:b0") {
+            Err(GamepadDbError::InvalidGuid) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_name() {
+        match GamepadLayout::parse("030000005e0400008e02000014010000,,a:b0") {
+            Err(GamepadDbError::MissingName) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bindings() {
+        for binding in &["a:x0", "a", "nope:b0", "+a:b0", "a:+b0", "dpup:h0"] {
+            let line = format!("030000005e0400008e02000014010000,Pad,{}", binding);
+            match GamepadLayout::parse(&line) {
+                Err(GamepadDbError::InvalidBinding(_)) => (),
+                other => panic!("unexpected {:?} for {}", other, binding),
+            }
+        }
+    }
+
+    #[test]
+    fn translates_through_layout() {
+        let layout = GamepadLayout::parse(XBOX).unwrap();
+        assert_eq!(
+            layout.translate_button(0, &RawState::Press),
+            vec![GamepadEvent::Button(GamepadButton::South, RawState::Press)]
+        );
+        assert_eq!(
+            layout.translate_axis(2, -1.0, &mut GamepadState::new()),
+            vec![GamepadEvent::Axis(GamepadAxis::LeftTrigger, 0.0)]
+        );
+        assert_eq!(
+            layout.translate_hat(0, 1, &mut GamepadState::new()),
+            vec![GamepadEvent::Button(GamepadButton::DPadUp, RawState::Press)]
+        );
+    }
+
+    #[test]
+    fn axis_buttons_change_on_edges_only() {
+        let layout =
+            GamepadLayout::parse("030000005e0400008e02000014010000,Pad,dpleft:-a0").unwrap();
+        let mut state = GamepadState::new();
+        let left = |state| vec![GamepadEvent::Button(GamepadButton::DPadLeft, state)];
+        assert_eq!(layout.translate_axis(0, -0.2, &mut state), vec![]);
+        assert_eq!(layout.translate_axis(0, -0.8, &mut state), left(RawState::Press));
+        assert_eq!(layout.translate_axis(0, -0.9, &mut state), vec![]);
+        assert_eq!(layout.translate_axis(0, 0.0, &mut state), left(RawState::Release));
+        assert_eq!(layout.translate_axis(0, 0.1, &mut state), vec![]);
+    }
+}
@@ -9,35 +9,72 @@ extern crate winit;
 #[macro_use]
 extern crate log;
 
+pub mod buffer;
+pub mod clock;
 pub mod event;
+pub mod explain;
+pub mod frame;
+pub mod gamepad;
+pub mod registry;
+pub mod touch;
 pub mod types;
 pub mod util;
 
 mod mapping;
 
 pub use event::*;
+pub use frame::InputFrame;
 pub use types::{ActionArgument, ActionMetadata, Context, MappedType, StateInfo};
 
-use types::{ActiveContext, StateStorage, WindowData};
+use buffer::{InputBuffer, DEFAULT_BUFFER_WINDOW};
+use clock::{Clock, SystemClock};
+use explain::InputTrace;
+use gamepad::{GamepadDatabase, GamepadState};
+use mapping::{releases, Environment, Input};
+use registry::{Predicate, RangeProcessor, Registry};
+use touch::TouchData;
+use types::{ActiveContext, ContextKind, ContextSwitch, Limit, RawState, RawType, Socd, SocdPair,
+            Mapping, MappingError, MappingHandle, StateStorage, TimedContext, VirtualControl,
+            WindowData};
 
+use std::any::Any;
 use std::collections::HashMap;
 
 use std::clone::Clone;
 use std::cmp::Eq;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::mem;
 
 use serde::de::DeserializeOwned;
 
 pub struct InputRebinder<ACTION, ID>
 where
-    ACTION: Hash + Eq + Clone,
+    ACTION: Hash + Eq + Clone + Debug,
     ID: Hash + Eq + Clone + Debug,
 {
     contexts: HashMap<ID, Context<ACTION, ID>>,
     active_contexts: Vec<ActiveContext<ID>>,
+    context_stack: Vec<ID>,
+    next_handle: u64,
+    one_shots: Vec<(ID, Option<RawType>)>,
+    momentary: Vec<(ID, Option<RawType>)>,
+    timed: Vec<TimedContext<ID>>,
+    state_origin: HashMap<ACTION, ID>,
+    groups: HashMap<String, Vec<ACTION>>,
+    masked_groups: Vec<String>,
+    gamepads: GamepadDatabase,
+    gamepad_devices: HashMap<winit::DeviceId, (String, GamepadState)>,
+    pending: Vec<Event<ACTION, ID>>,
     state_storage: StateStorage<ACTION>,
     frame_data: WindowData,
+    registry: Registry,
+    frame: InputFrame<ACTION>,
+    buffer: InputBuffer<ACTION>,
+    explain: bool,
+    traces: Vec<InputTrace<ID>>,
+    clock: Box<dyn Clock>,
+    time: Timestamp,
 }
 
 impl<ACTION, ID> InputRebinder<ACTION, ID>
@@ -46,49 +83,489 @@ where
     ID: Hash + Eq + Clone + Debug + DeserializeOwned,
 {
     pub fn new(size: (f64, f64)) -> InputRebinder<ACTION, ID> {
+        let now = SystemClock.now();
         InputRebinder {
             contexts: HashMap::default(),
             active_contexts: Vec::default(),
+            context_stack: Vec::default(),
+            next_handle: 0,
+            one_shots: Vec::default(),
+            momentary: Vec::default(),
+            timed: Vec::default(),
+            state_origin: HashMap::default(),
+            groups: HashMap::default(),
+            masked_groups: Vec::default(),
+            gamepads: GamepadDatabase::new(),
+            gamepad_devices: HashMap::default(),
+            pending: Vec::default(),
             state_storage: StateStorage::new(),
             frame_data: WindowData {
                 size,
                 cursor_position: None,
+                touches: TouchData::default(),
+                held_keys: Vec::default(),
+                held_buttons: Vec::default(),
+                repeated: false,
+                time: 0.0,
+                motion: None,
+                scroll: None,
+                last_update: now,
+                update_interval: 0.0,
             },
+            registry: Registry::new(),
+            frame: InputFrame::new(),
+            buffer: InputBuffer::new(DEFAULT_BUFFER_WINDOW),
+            explain: false,
+            traces: Vec::default(),
+            clock: Box::new(SystemClock),
+            time: now,
         }
     }
 
+    pub fn with_clock<C>(&mut self, clock: C) -> &mut Self
+    where
+        C: Clock + 'static,
+    {
+        self.time = clock.now();
+        self.frame_data.last_update = self.time;
+        self.clock = Box::new(clock);
+        self
+    }
+
     pub fn with_context(&mut self, mut context: Context<ACTION, ID>) -> &mut Self {
         context.sanitize();
+        for parent_id in context.parents.clone() {
+            match self.contexts.get(&parent_id) {
+                Some(parent) if parent_id != context.id => context.inherit(parent),
+                _ => warn!("Context {:?} has unknown parent {:?}", context.id, parent_id),
+            }
+        }
+        for mapping in context.mappings.iter_mut() {
+            mapping.handle = self.new_handle();
+        }
         self.contexts.insert(context.id.clone(), context);
+        self.refresh_masked();
         self
     }
 
+    pub fn mapping(&self, handle: MappingHandle) -> Option<&Mapping<ACTION, ID>> {
+        self.contexts
+            .values()
+            .flat_map(|c| c.mappings.iter())
+            .find(|m| m.handle == handle)
+    }
+
+    pub fn add_mapping(
+        &mut self,
+        context_id: &ID,
+        mut mapping: Mapping<ACTION, ID>,
+    ) -> Result<MappingHandle, MappingError> {
+        if !self.contexts.contains_key(context_id) {
+            return Err(MappingError::UnknownContext);
+        }
+        prepare_mapping(&mut mapping)?;
+        mapping.handle = self.new_handle();
+        let handle = mapping.handle;
+        if let Some(context) = self.contexts.get_mut(context_id) {
+            context.mappings.push(mapping);
+        }
+        self.refresh_masked();
+        Ok(handle)
+    }
+
+    pub fn edit_mapping<F>(&mut self, handle: MappingHandle, edit: F) -> Result<(), MappingError>
+    where
+        F: FnOnce(&mut Mapping<ACTION, ID>),
+    {
+        let mapping = self.contexts
+            .values_mut()
+            .flat_map(|c| c.mappings.iter_mut())
+            .find(|m| m.handle == handle)
+            .ok_or(MappingError::UnknownMapping)?;
+        let mut edited = mapping.clone();
+        edit(&mut edited);
+        edited.handle = handle;
+        prepare_mapping(&mut edited)?;
+        let previous = mem::replace(mapping, edited);
+        self.release_mapping(&previous);
+        self.refresh_masked();
+        Ok(())
+    }
+
+    pub fn remove_mapping(
+        &mut self,
+        handle: MappingHandle,
+    ) -> Result<Mapping<ACTION, ID>, MappingError> {
+        for context in self.contexts.values_mut() {
+            if let Some(index) = context.mappings.iter().position(|m| m.handle == handle) {
+                let mapping = context.mappings.remove(index);
+                self.release_mapping(&mapping);
+                self.refresh_masked();
+                return Ok(mapping);
+            }
+        }
+        Err(MappingError::UnknownMapping)
+    }
+
+    fn release_mapping(&mut self, mapping: &Mapping<ACTION, ID>) {
+        let now = self.time;
+        self.state_origin.remove(&mapping.action);
+        if let Some(duration) = self.state_storage
+            .release_mapping(mapping.handle, &mapping.action, now)
+        {
+            self.pending.push(Event::Controller(
+                mapping.action.clone(),
+                ActionType::State(StateAction::Deactivated, duration),
+                Vec::default(),
+                now,
+            ));
+        }
+    }
+
+    fn new_handle(&mut self) -> MappingHandle {
+        self.next_handle += 1;
+        MappingHandle(self.next_handle)
+    }
+
     pub fn with_contexts(&mut self, contexts: &mut Vec<Context<ACTION, ID>>) -> &mut Self {
         if contexts.len() == 0 {
             return self;
         }
-        for c in contexts.drain(..) {
-            self.with_context(c);
+        let mut pending: Vec<Context<ACTION, ID>> = contexts.drain(..).collect();
+        while !pending.is_empty() {
+            let ids: Vec<ID> = pending.iter().map(|c| c.id.clone()).collect();
+            let (mut ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|c| {
+                c.parents
+                    .iter()
+                    .all(|p| *p == c.id || !ids.contains(p))
+            });
+            pending = waiting;
+            if ready.is_empty() {
+                warn!("Cyclic context inheritance between {:?}", ids);
+                ready = pending.drain(..).collect();
+            }
+            for c in ready {
+                self.with_context(c);
+            }
         }
         debug!("{:?}", self.contexts);
         self
     }
 
+    pub fn with_processor<P>(&mut self, name: &str, processor: P) -> &mut Self
+    where
+        P: RangeProcessor + 'static,
+    {
+        self.registry.with_processor(name, processor);
+        self
+    }
+
+    pub fn with_predicate<P>(&mut self, name: &str, predicate: P) -> &mut Self
+    where
+        P: Predicate + 'static,
+    {
+        self.registry.with_predicate(name, predicate);
+        self
+    }
+
+    pub fn with_limit(&mut self, action: ACTION, limit: Limit) -> &mut Self {
+        self.state_storage.limits.insert(action, limit);
+        self
+    }
+
+    pub fn with_buffer_window(&mut self, window: f64) -> &mut Self {
+        self.buffer.set_window(window);
+        self
+    }
+
+    pub fn with_action_buffer_window(&mut self, action: ACTION, window: f64) -> &mut Self {
+        self.buffer.set_action_window(action, window);
+        self
+    }
+
+    pub fn with_socd(&mut self, first: ACTION, second: ACTION, mode: Socd<ACTION>) -> &mut Self {
+        if let Socd::Priority(ref action) = mode {
+            if *action != first && *action != second {
+                warn!("SOCD priority {:?} is not part of {:?}/{:?}", action, first, second);
+                return self;
+            }
+        }
+        self.state_storage.socd.push(SocdPair {
+            first,
+            second,
+            mode,
+        });
+        self
+    }
+
+    pub fn with_explain(&mut self, explain: bool) -> &mut Self {
+        self.explain = explain;
+        self.traces.clear();
+        self
+    }
+
+    pub fn with_gamepad_database(&mut self, database: GamepadDatabase) -> &mut Self {
+        self.gamepads = database;
+        self
+    }
+
+    pub fn with_gamepad(&mut self, device_id: winit::DeviceId, guid: &str) -> &mut Self {
+        if self.gamepads.get(guid).is_none() {
+            warn!("No gamepad layout for {:?} ({})", device_id, guid);
+        }
+        self.gamepad_devices
+            .insert(device_id, (guid.to_lowercase(), GamepadState::new()));
+        self
+    }
+
+    pub fn remove_gamepad(&mut self, device_id: &winit::DeviceId) {
+        self.gamepad_devices.remove(device_id);
+    }
+
+    pub fn with_group(&mut self, name: &str, actions: Vec<ACTION>) -> &mut Self {
+        self.groups
+            .entry(name.to_string())
+            .or_insert_with(Vec::default)
+            .extend(actions);
+        self.refresh_masked();
+        self
+    }
+
+    pub fn mask_group(&mut self, name: &str) {
+        if !self.is_group_masked(name) {
+            self.masked_groups.push(name.to_string());
+            self.refresh_masked();
+        }
+    }
+
+    pub fn unmask_group(&mut self, name: &str) {
+        self.masked_groups.retain(|g| g != name);
+        self.refresh_masked();
+    }
+
+    pub fn is_group_masked(&self, name: &str) -> bool {
+        self.masked_groups.iter().any(|g| g == name)
+    }
+
+    pub fn is_masked(&self, action: &ACTION) -> bool {
+        self.state_storage.masked.contains(action)
+    }
+
+    fn refresh_masked(&mut self) {
+        let now = self.time;
+        let mut masked: Vec<ACTION> = Vec::default();
+        {
+            let masked_groups = &self.masked_groups;
+            let declared = masked_groups
+                .iter()
+                .filter_map(|g| self.groups.get(g))
+                .flat_map(|actions| actions.iter());
+            let metadata = self.contexts
+                .values()
+                .flat_map(|c| c.mappings.iter())
+                .map(|m| &m.action)
+                .filter(|a| a.groups().iter().any(|g| masked_groups.contains(g)));
+            for action in declared.chain(metadata) {
+                if !masked.contains(action) {
+                    masked.push(action.clone());
+                }
+            }
+        }
+        let newly_masked: Vec<ACTION> = masked
+            .iter()
+            .filter(|a| !self.state_storage.masked.contains(a))
+            .cloned()
+            .collect();
+        for (action, duration) in self.state_storage.mask(masked, now) {
+            self.state_origin.remove(&action);
+            self.pending.push(Event::Controller(
+                action,
+                ActionType::State(StateAction::Deactivated, duration),
+                Vec::default(),
+                now,
+            ));
+        }
+        for action in newly_masked {
+            self.buffer.remove(&action);
+            if let MappedType::Range = action.mapped_type() {
+                self.pending.push(Event::Controller(
+                    action,
+                    ActionType::Range((0.0, 0.0)),
+                    Vec::default(),
+                    now,
+                ));
+            }
+        }
+    }
+
     pub fn activate_context(&mut self, context_id: &ID, priority: u32) {
+        self.insert_context(ActiveContext::new(priority, context_id));
+    }
+
+    pub fn activate_global_context(&mut self, context_id: &ID) {
+        self.insert_context(ActiveContext::with_kind(0, context_id, ContextKind::Global));
+    }
+
+    pub fn deactivate_context(&mut self, context_id: &ID) {
         if let Some(_) = self.contexts.get(context_id) {
-            let pos = self.active_contexts
-                .binary_search_by(|p| priority.cmp(&p.priority))
-                .unwrap_or_else(|pos| pos);
+            let before = self.active_contexts.len();
             self.active_contexts
-                .insert(pos, ActiveContext::new(priority, context_id));
+                .retain(|ac| ac.context_id != *context_id);
+            self.context_stack.retain(|id| id != context_id);
+            self.one_shots.retain(|&(ref id, _)| id != context_id);
+            self.momentary.retain(|&(ref id, _)| id != context_id);
+            self.timed.retain(|t| t.context_id != *context_id);
+            if self.active_contexts.len() != before {
+                self.release_context_states(context_id);
+                self.pending.push(Event::Context(
+                    ContextAction::Deactivated,
+                    context_id.clone(),
+                    self.time,
+                ));
+            }
         }
         debug!("{:?}", self.active_contexts);
     }
 
-    pub fn deactivate_context(&mut self, context_id: &ID) {
-        if let Some(_) = self.contexts.get(context_id) {
+    pub fn is_context_active(&self, context_id: &ID) -> bool {
+        self.active_contexts
+            .iter()
+            .any(|ac| ac.context_id == *context_id)
+    }
+
+    fn release_context_states(&mut self, context_id: &ID) {
+        let now = self.time;
+        let actions: Vec<ACTION> = self.state_origin
+            .iter()
+            .filter(|&(_, id)| id == context_id)
+            .map(|(action, _)| action.clone())
+            .collect();
+        for action in actions {
+            self.state_origin.remove(&action);
+            if let Some(duration) = self.state_storage.release(&action, now) {
+                self.pending.push(Event::Controller(
+                    action,
+                    ActionType::State(StateAction::Deactivated, duration),
+                    Vec::default(),
+                    now,
+                ));
+            }
+        }
+    }
+
+    pub fn push_context(&mut self, context_id: &ID) {
+        self.push(context_id, ContextKind::Normal);
+    }
+
+    pub fn push_modal_context(&mut self, context_id: &ID) {
+        self.push(context_id, ContextKind::Modal);
+    }
+
+    pub fn push_timed_context(&mut self, context_id: &ID, timeout: f64, complete_on_action: bool) {
+        self.push_context(context_id);
+        if self.is_context_active(context_id) {
+            self.timed.push(TimedContext {
+                context_id: context_id.clone(),
+                deadline: self.time + timeout,
+                complete_on_action,
+            });
+        }
+    }
+
+    pub fn pop_context(&mut self) -> Option<ID> {
+        let context_id = self.context_stack.pop()?;
+        self.deactivate_context(&context_id);
+        Some(context_id)
+    }
+
+    fn push(&mut self, context_id: &ID, kind: ContextKind) {
+        if !self.contexts.contains_key(context_id) {
+            return;
+        }
+        let priority = self.active_contexts
+            .iter()
+            .filter(|ac| ac.kind != ContextKind::Global)
+            .map(|ac| ac.priority.saturating_add(1))
+            .max()
+            .unwrap_or(0);
+        self.deactivate_context(context_id);
+        self.context_stack.push(context_id.clone());
+        self.insert_context(ActiveContext::with_kind(priority, context_id, kind));
+    }
+
+    fn expire_contexts(&mut self) {
+        let now = self.time;
+        let expired: Vec<ID> = self.timed
+            .iter()
+            .filter(|t| t.deadline <= now)
+            .map(|t| t.context_id.clone())
+            .collect();
+        for context_id in expired {
+            self.pending
+                .push(Event::Context(ContextAction::TimedOut, context_id.clone(), now));
+            self.deactivate_context(&context_id);
+        }
+    }
+
+    fn complete_context(&mut self, context_id: &ID) {
+        let now = self.time;
+        let complete = self.timed
+            .iter()
+            .any(|t| t.context_id == *context_id && t.complete_on_action && t.deadline > now);
+        if complete {
+            self.pending.push(Event::Context(
+                ContextAction::Succeeded,
+                context_id.clone(),
+                self.time,
+            ));
+            self.deactivate_context(context_id);
+        }
+    }
+
+    fn switch_context(&mut self, switch: ContextSwitch<ID>) {
+        match switch {
+            ContextSwitch::Activate(context_id, priority) => {
+                self.activate_context(&context_id, priority)
+            }
+            ContextSwitch::Deactivate(context_id) => self.deactivate_context(&context_id),
+            ContextSwitch::Toggle(context_id) | ContextSwitch::Momentary(context_id) => {
+                if self.is_context_active(&context_id) {
+                    self.deactivate_context(&context_id);
+                } else {
+                    self.push_context(&context_id);
+                }
+            }
+            ContextSwitch::Push(context_id) => self.push_context(&context_id),
+            ContextSwitch::PushModal(context_id) => self.push_modal_context(&context_id),
+            ContextSwitch::Pop => {
+                self.pop_context();
+            }
+            ContextSwitch::OneShot(context_id) => {
+                self.push_context(&context_id);
+                if self.is_context_active(&context_id) {
+                    self.one_shots.push((context_id, None));
+                }
+            }
+        }
+    }
+
+    fn insert_context(&mut self, active_context: ActiveContext<ID>) {
+        if let Some(_) = self.contexts.get(&active_context.context_id) {
+            // Reactivating an active context only updates its priority and kind.
+            let before = self.active_contexts.len();
             self.active_contexts
-                .retain(|ac| ac.context_id != *context_id);
+                .retain(|ac| ac.context_id != active_context.context_id);
+            if self.active_contexts.len() == before {
+                self.pending.push(Event::Context(
+                    ContextAction::Activated,
+                    active_context.context_id.clone(),
+                    self.time,
+                ));
+            }
+            let pos = self.active_contexts
+                .binary_search_by(|p| active_context.priority.cmp(&p.priority))
+                .unwrap_or_else(|pos| pos);
+            self.active_contexts.insert(pos, active_context);
         }
         debug!("{:?}", self.active_contexts);
     }
@@ -101,27 +578,83 @@ where
         self.state_storage.is_active(state)
     }
 
+    pub fn frame(&self) -> &InputFrame<ACTION> {
+        &self.frame
+    }
+
+    // Clears the just pressed and released flags; call once per tick before process and update.
+    pub fn begin_frame(&mut self) {
+        self.frame.begin();
+    }
+
+    pub fn context(&self, context_id: &ID) -> Option<&Context<ACTION, ID>> {
+        self.contexts.get(context_id)
+    }
+
+    pub fn contexts(&self) -> Vec<&Context<ACTION, ID>> {
+        self.contexts.values().collect()
+    }
+
+    pub fn mappings(&self, context_id: &ID) -> Option<&[Mapping<ACTION, ID>]> {
+        self.contexts.get(context_id).map(|c| c.mappings.as_slice())
+    }
+
+    pub fn active_contexts(&self) -> &[ActiveContext<ID>] {
+        &self.active_contexts
+    }
+
+    pub fn enabled_contexts(&self) -> Vec<&ActiveContext<ID>> {
+        enabled_contexts(&self.active_contexts)
+    }
+
+    pub fn context_stack(&self) -> &[ID] {
+        &self.context_stack
+    }
+
+    pub fn state_storage(&self) -> &StateStorage<ACTION> {
+        &self.state_storage
+    }
+
+    pub fn window_data(&self) -> &WindowData {
+        &self.frame_data
+    }
+
+    pub fn traces(&self) -> &[InputTrace<ID>] {
+        &self.traces
+    }
+
+    pub fn is_buffered(&self, action: &ACTION) -> bool {
+        !self.is_masked(action) && self.buffer.is_buffered(action, self.time)
+    }
+
+    pub fn consume_buffered(&mut self, action: &ACTION) -> bool {
+        !self.is_masked(action) && self.buffer.consume(action, self.time)
+    }
+
     fn process_window_input(&self, raw_input: &winit::Event) -> Option<Event<ACTION, ID>> {
         use winit::{Event as WEvent, WindowEvent};
         match *raw_input {
             WEvent::WindowEvent {
                 event: WindowEvent::Resized(x, y),
                 ..
-            } => Some(Event::Resize(x, y)),
+            } => Some(Event::Resize(x, y, self.time)),
 
             WEvent::WindowEvent {
                 event: WindowEvent::Focused(b),
                 ..
-            } => Some(Event::Focus(if b {
-                FocusAction::Enter
-            } else {
-                FocusAction::Exit
-            })),
+            } => Some(Event::Focus(
+                if b {
+                    FocusAction::Enter
+                } else {
+                    FocusAction::Exit
+                },
+                self.time,
+            )),
 
             WEvent::WindowEvent {
                 event: WindowEvent::Closed,
                 ..
-            } => Some(Event::Close),
+            } => Some(Event::Close(self.time)),
 
             _ => None,
         }
@@ -131,34 +664,375 @@ where
         &mut self,
         raw_input: &winit::Event,
         next: &mut WindowData,
-    ) -> Option<Event<ACTION, ID>> {
-        let state_storage = &mut self.state_storage;
-        let contexts = &self.contexts;
-        self.active_contexts
+        user: &dyn Any,
+    ) -> Vec<Event<ACTION, ID>> {
+        if let winit::Event::WindowEvent {
+            event: winit::WindowEvent::Focused(false),
+            ..
+        } = *raw_input
+        {
+            next.held_keys.clear();
+            next.held_buttons.clear();
+            for (layer, _) in self.momentary.clone() {
+                self.deactivate_context(&layer);
+            }
+            let mut events: Vec<Event<ACTION, ID>> = next.touches
+                .release_all()
+                .into_iter()
+                .flat_map(|v| self.process_mapped_input(&Input::Virtual(v), next, user))
+                .collect();
+            events.extend(self.release_states());
+            return events;
+        }
+        let size = next.size;
+        next.update_held(raw_input);
+        let mut inputs = vec![Input::Raw(raw_input)];
+        {
+            let contexts = &self.contexts;
+            let controls: Vec<&VirtualControl> = enabled_contexts(&self.active_contexts)
+                .into_iter()
+                .filter_map(|ac| contexts.get(&ac.context_id))
+                .flat_map(|c| c.virtual_controls.iter())
+                .collect();
+            inputs.extend(
+                next.touches
+                    .update_virtual(raw_input, size, &controls)
+                    .into_iter()
+                    .map(Input::Virtual),
+            );
+        }
+        inputs.extend(
+            next.touches
+                .update(raw_input, size, self.time)
+                .into_iter()
+                .map(Input::Touch),
+        );
+        if let winit::Event::DeviceEvent {
+            device_id,
+            ref event,
+        } = *raw_input
+        {
+            if let Some(&mut (ref guid, ref mut state)) = self.gamepad_devices.get_mut(&device_id) {
+                if let Some(layout) = self.gamepads.get(guid) {
+                    inputs.extend(layout.translate(event, state).into_iter().map(Input::Gamepad));
+                }
+            }
+        }
+        for input in inputs.iter().filter(|input| input.state() == RawState::Press) {
+            if let Some(raw_type) = input.raw_type() {
+                for one_shot in self.one_shots.iter_mut().filter(|o| o.1.is_none()) {
+                    one_shot.1 = Some(raw_type.clone());
+                }
+            }
+        }
+        let mut events: Vec<Event<ACTION, ID>> = inputs
+            .iter()
+            .flat_map(|input| self.process_mapped_input(input, next, user))
+            .collect();
+        let finished: Vec<ID> = self.one_shots
             .iter()
-            .filter_map(|ac| {
-                contexts
-                    .get(&ac.context_id)
-                    .and_then(|c| c.process(raw_input, state_storage, next))
+            .filter(|o| {
+                o.1
+                    .as_ref()
+                    .map_or(false, |r| inputs.iter().any(|input| releases(r, input)))
             })
-            .next()
+            .map(|o| o.0.clone())
+            .collect();
+        let released: Vec<ID> = self.momentary
+            .iter()
+            .filter(|m| {
+                m.1
+                    .as_ref()
+                    .map_or(false, |r| inputs.iter().any(|input| releases(r, input)))
+            })
+            .map(|m| m.0.clone())
+            .collect();
+        for context_id in finished.into_iter().chain(released) {
+            self.deactivate_context(&context_id);
+        }
+        events.extend(self.pending.drain(..));
+        next.update(raw_input);
+        events
+    }
+
+    fn release_states(&mut self) -> Vec<Event<ACTION, ID>> {
+        let now = self.time;
+        let mut events: Vec<Event<ACTION, ID>> = self.state_storage
+            .release_all(now)
+            .into_iter()
+            .map(|(action, duration)| {
+                Event::Controller(
+                    action,
+                    ActionType::State(StateAction::Deactivated, duration),
+                    Vec::default(),
+                    now,
+                )
+            })
+            .collect();
+        events.extend(
+            self.state_storage
+                .reset_ranges(now)
+                .into_iter()
+                .map(|action| {
+                    Event::Controller(action, ActionType::Range((0.0, 0.0)), Vec::default(), now)
+                }),
+        );
+        events
+    }
+
+    fn process_mapped_input(
+        &mut self,
+        input: &Input,
+        next: &mut WindowData,
+        user: &dyn Any,
+    ) -> Vec<Event<ACTION, ID>> {
+        self.state_storage.release_inputs(input);
+        let mut context_traces = Vec::default();
+        let result = {
+            let state_storage = &mut self.state_storage;
+            let contexts = &self.contexts;
+            let env = Environment {
+                active_contexts: &self.active_contexts,
+                registry: &self.registry,
+                user,
+            };
+            let now = self.time;
+            let explain = self.explain;
+            enabled_contexts(&self.active_contexts)
+                .into_iter()
+                .filter_map(|ac| {
+                    let context = contexts.get(&ac.context_id)?;
+                    let traces = if explain {
+                        Some(&mut context_traces)
+                    } else {
+                        None
+                    };
+                    context
+                        .process(input, state_storage, next, &env, now, traces)
+                        .map(|(m, event)| {
+                            let switch = m.switch.clone();
+                            (ac.context_id.clone(), m.handle, switch, m.emit_action, event)
+                        })
+                })
+                .next()
+        };
+        if self.explain {
+            self.traces.push(InputTrace {
+                input: input.raw_type(),
+                state: input.state(),
+                time: self.time,
+                contexts: context_traces,
+                winner: result.as_ref().map(|r| (r.0.clone(), r.1)),
+            });
+        }
+        let (context_id, event) = match result {
+            Some((_, _, _, _, None)) | None => return Vec::default(),
+            Some((context_id, _, Some(switch), emit_action, Some(event))) => {
+                match switch {
+                    ContextSwitch::Momentary(layer) => if input.state() == RawState::Press {
+                        if !self.is_context_active(&layer) {
+                            self.push_context(&layer);
+                            self.momentary.push((layer, input.raw_type()));
+                        }
+                    } else {
+                        self.deactivate_context(&layer);
+                    },
+                    switch => if is_trigger(&event) {
+                        self.switch_context(switch);
+                    },
+                }
+                if !emit_action {
+                    return Vec::default();
+                }
+                (context_id, event)
+            }
+            Some((context_id, _, None, _, Some(event))) => (context_id, event),
+        };
+        let events = match event {
+            Event::Controller(ref action, ActionType::State(ref state_action, _), ref args, now)
+                if self.state_storage.is_socd(action) =>
+            {
+                self.state_storage
+                    .resolve_socd(action, state_action, now)
+                    .into_iter()
+                    .map(|(side, state_action, duration)| {
+                        let args = if side == *action {
+                            args.clone()
+                        } else {
+                            Vec::default()
+                        };
+                        let action_type = ActionType::State(state_action, duration);
+                        Event::Controller(side, action_type, args, now)
+                    })
+                    .collect()
+            }
+            event => vec![event],
+        };
+        for e in events.iter() {
+            if let Event::Controller(ref action, ActionType::State(StateAction::Activated, _), ..) =
+                *e
+            {
+                self.state_origin.insert(action.clone(), context_id.clone());
+            }
+        }
+        if events.iter().any(is_trigger) {
+            self.complete_context(&context_id);
+        }
+        events
+    }
+
+    pub fn update(&mut self, now: Timestamp) -> Vec<Event<ACTION, ID>> {
+        self.update_with(now, &())
+    }
+
+    // Time never moves backwards, so a stale now is clamped to the last sampled time.
+    pub fn update_with(&mut self, now: Timestamp, user: &dyn Any) -> Vec<Event<ACTION, ID>> {
+        self.time = self.time.max(now);
+        let now = self.time;
+        self.traces.clear();
+        self.expire_contexts();
+        let mut next = self.frame_data.clone();
+        next.time = now;
+        next.repeated = false;
+        let mut events: Vec<Event<ACTION, ID>> = self.pending.drain(..).collect();
+        let gestures: Vec<Event<ACTION, ID>> = next.touches
+            .update_time(now)
+            .into_iter()
+            .flat_map(|g| self.process_mapped_input(&Input::Touch(g), &mut next, user))
+            .collect();
+        events.extend(gestures);
+        self.frame_data = next;
+        for ac in enabled_contexts(&self.active_contexts) {
+            if let Some(context) = self.contexts.get(&ac.context_id) {
+                let state_storage = &mut self.state_storage;
+                events.extend(context.update(now, state_storage, &self.frame_data, &self.registry));
+            }
+        }
+        self.frame_data.reset_impulses(now);
+        events.extend(
+            self.state_storage
+                .expire_holds(now)
+                .into_iter()
+                .map(|action| {
+                    Event::Controller(action, ActionType::Action, Vec::default(), now)
+                }),
+        );
+        events.extend(
+            self.state_storage
+                .expire_repeats(now)
+                .into_iter()
+                .map(|(action, time)| {
+                    Event::Controller(action, ActionType::Action, Vec::default(), time)
+                }),
+        );
+        events.extend(
+            self.state_storage
+                .states
+                .iter()
+                .filter(|&(_, info)| info.active)
+                .map(|(action, info)| {
+                    Event::Controller(
+                        action.clone(),
+                        ActionType::State(StateAction::Active, now - info.start_time),
+                        Vec::default(),
+                        now,
+                    )
+                }),
+        );
+        self.frame.update(&events, now);
+        self.buffer.update(&events, now);
+        events
+    }
+
+    // Moves time forward by dt, advancing clocks that support it, such as ManualClock.
+    pub fn tick(&mut self, dt: f64) -> Vec<Event<ACTION, ID>> {
+        self.clock.advance(dt);
+        let now = self.time + dt.max(0.0);
+        self.update(now)
+    }
+
+    fn sample_clock(&mut self) {
+        self.time = self.time.max(self.clock.now());
     }
 
     pub fn process(&mut self, raw_input: &Vec<winit::Event>) -> Vec<Event<ACTION, ID>> {
-        if raw_input.len() <= 0 {
-            return Vec::default();
+        self.process_with(raw_input, &())
+    }
+
+    pub fn process_with(
+        &mut self,
+        raw_input: &Vec<winit::Event>,
+        user: &dyn Any,
+    ) -> Vec<Event<ACTION, ID>> {
+        self.sample_clock();
+        self.traces.clear();
+        self.expire_contexts();
+        let mut events: Vec<Event<ACTION, ID>> = self.pending.drain(..).collect();
+        if raw_input.len() > 0 {
+            events.extend(self.process_raw_input(raw_input, user));
         }
+        self.frame.update(&events, self.time);
+        self.buffer.update(&events, self.time);
+        events
+    }
+
+    fn process_raw_input(
+        &mut self,
+        raw_input: &Vec<winit::Event>,
+        user: &dyn Any,
+    ) -> Vec<Event<ACTION, ID>> {
         let mut next = self.frame_data.clone();
-        let mut window_input: Vec<Event<ACTION, ID>> = raw_input
+        next.time = self.time;
+        let mut events: Vec<Event<ACTION, ID>> = raw_input
             .iter()
             .filter_map(|ri| self.process_window_input(ri))
             .collect();
         let controller_input: Vec<Event<ACTION, ID>> = raw_input
             .iter()
-            .filter_map(|ri| self.process_controller_input(ri, &mut next))
+            .flat_map(|ri| self.process_controller_input(ri, &mut next, user))
             .collect();
-        window_input.extend(controller_input);
+        events.extend(controller_input);
+        self.expire_contexts();
+        events.extend(self.pending.drain(..));
         self.frame_data = next;
-        window_input
+        events
     }
 }
+
+fn enabled_contexts<ID>(active_contexts: &[ActiveContext<ID>]) -> Vec<&ActiveContext<ID>>
+where
+    ID: Debug + Clone,
+{
+    let mut blocked = false;
+    active_contexts
+        .iter()
+        .filter(|ac| {
+            let enabled = !blocked || ac.kind == ContextKind::Global;
+            blocked = blocked || ac.kind == ContextKind::Modal;
+            enabled
+        })
+        .collect()
+}
+
+fn is_trigger<ACTION, ID>(event: &Event<ACTION, ID>) -> bool
+where
+    ACTION: Debug,
+    ID: Debug + Clone,
+{
+    match *event {
+        Event::Controller(_, ActionType::Action, ..) |
+        Event::Controller(_, ActionType::State(StateAction::Activated, _), ..) => true,
+        _ => false,
+    }
+}
+
+fn prepare_mapping<ACTION, ID>(mapping: &mut Mapping<ACTION, ID>) -> Result<(), MappingError>
+where
+    ACTION: Clone + ActionMetadata,
+    ID: Clone,
+{
+    mapping.mapped_type = Some(mapping.action.mapped_type());
+    mapping.action_args = mapping.action.args();
+    mapping.sanitize();
+    mapping.validate()
+}
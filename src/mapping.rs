@@ -1,14 +1,64 @@
 use super::event::*;
+use super::gamepad::GamepadEvent;
+use super::explain::{ContextTrace, MappingTrace, MatchResult};
+use super::registry::Registry;
+use super::touch::{Gesture, VirtualInput};
 use super::types::*;
 
-use time;
 use winit;
 
+use std::any::Any;
 use std::clone::Clone;
+use std::collections::HashMap;
 use std::cmp::Eq;
 use std::fmt::Debug;
 use std::hash::Hash;
 
+const MIN_REPEAT_INTERVAL: f64 = 0.001;
+
+pub struct Environment<'a, ID: 'a + Debug + Clone> {
+    pub active_contexts: &'a [ActiveContext<ID>],
+    pub registry: &'a Registry,
+    pub user: &'a dyn Any,
+}
+
+#[derive(Debug, Clone)]
+pub enum Input<'a> {
+    Raw(&'a winit::Event),
+    Touch(Gesture),
+    Virtual(VirtualInput),
+    Gamepad(GamepadEvent),
+}
+
+impl<'a> Input<'a> {
+    pub fn raw(&self) -> Option<&'a winit::Event> {
+        match *self {
+            Input::Raw(raw_input) => Some(raw_input),
+            _ => None,
+        }
+    }
+
+    pub fn state(&self) -> RawState {
+        get_raw_state(self)
+    }
+
+    pub fn raw_type(&self) -> Option<RawType> {
+        match *self {
+            Input::Raw(raw_input) => raw_type(raw_input),
+            Input::Touch(ref gesture) => Some(RawType::Touch(gesture.gesture.clone())),
+            Input::Virtual(ref virtual_input) => {
+                Some(RawType::Virtual(virtual_input.name.clone()))
+            }
+            Input::Gamepad(GamepadEvent::Button(ref button, _)) => {
+                Some(RawType::GamepadButton(button.clone()))
+            }
+            Input::Gamepad(GamepadEvent::Axis(ref axis, _)) => {
+                Some(RawType::GamepadAxis(axis.clone()))
+            }
+        }
+    }
+}
+
 impl<ACTION, ID> Context<ACTION, ID>
 where
     ACTION: Hash + Eq + Debug + Clone,
@@ -16,85 +66,708 @@ where
 {
     pub fn process(
         &self,
-        raw_input: &winit::Event,
+        input: &Input,
+        state_storage: &mut StateStorage<ACTION>,
+        frame_data: &mut WindowData,
+        env: &Environment<ID>,
+        now: Timestamp,
+        traces: Option<&mut Vec<ContextTrace<ID>>>,
+    ) -> Option<(&Mapping<ACTION, ID>, Option<Event<ACTION, ID>>)> {
+        let mut mappings = traces.as_ref().map(|_| Vec::default());
+        let result = self.resolve(input, state_storage, frame_data, env, now, mappings.as_mut());
+        if let (Some(traces), Some(mappings)) = (traces, mappings) {
+            traces.push(ContextTrace {
+                context_id: self.id.clone(),
+                mappings,
+            });
+        }
+        result
+    }
+
+    fn resolve(
+        &self,
+        input: &Input,
         state_storage: &mut StateStorage<ACTION>,
         frame_data: &mut WindowData,
-    ) -> Option<Event<ACTION, ID>> {
-        let event = process_internal(&self, raw_input, state_storage, frame_data);
-        if let Some(Event::Controller(ref action, ActionType::State(ref state_action, _), _)) =
-            event
+        env: &Environment<ID>,
+        now: Timestamp,
+        mut trace: Option<&mut Vec<MappingTrace>>,
+    ) -> Option<(&Mapping<ACTION, ID>, Option<Event<ACTION, ID>>)> {
+        let mut matched = None;
+        let mut crossed = Vec::default();
+        for m in self.mappings.iter() {
+            if matched.is_some() && m.threshold.is_none() {
+                continue;
+            }
+            let result = check_mapping(m, input, state_storage, frame_data, env);
+            match result {
+                MatchResult::Matched |
+                MatchResult::StateMismatch |
+                MatchResult::RepeatSuppressed |
+                MatchResult::Unmapped if m.threshold.is_some() => crossed.push(m),
+                _ => (),
+            }
+            if matched.is_some() {
+                continue;
+            }
+            let found = match result {
+                MatchResult::Matched | MatchResult::RepeatSuppressed => Some(result.clone()),
+                _ => None,
+            };
+            if let Some(ref mut trace) = trace {
+                trace.push(MappingTrace {
+                    handle: m.handle,
+                    result,
+                });
+            }
+            if let Some(result) = found {
+                matched = Some((m, result));
+            }
+        }
+        let event = match matched {
+            Some((mapping, MatchResult::Matched)) => {
+                mapping_event(mapping, input, &self.id, frame_data, state_storage, now)
+            }
+            _ => None,
+        };
+        for m in crossed {
+            if let Some(edge) = threshold_state(m, input, frame_data, state_storage) {
+                latch(m, &edge, state_storage);
+            }
+        }
+        let (mapping, mut event) = match (matched, event) {
+            (Some((mapping, _)), Some(event)) => (mapping, event),
+            (Some((mapping, _)), None) => return Some((mapping, None)),
+            (None, _) => return None,
+        };
+        if mapping.toggle {
+            event = match toggle_event(event, state_storage) {
+                Some(event) => event,
+                None => {
+                    skip(trace, MatchResult::ToggleSkipped);
+                    return Some((mapping, None));
+                }
+            };
+        }
+        event = match limit_event(mapping, event, state_storage, now) {
+            Some(event) => event,
+            None => {
+                skip(trace, MatchResult::Limited);
+                return Some((mapping, None));
+            }
+        };
+        if let Event::Controller(_, ActionType::Blocked(_), ..) = event {
+            return Some((mapping, Some(event)));
+        }
+        if mapping.switch.is_some() && !mapping.emit_action {
+            return Some((mapping, Some(event)));
+        }
+        if let Some(duration) = mapping.hold {
+            start_hold(mapping, duration, state_storage, now);
+            skip(trace, MatchResult::HoldPending);
+            return Some((mapping, None));
+        }
+        if let Some(ref repeat) = mapping.repeat {
+            start_repeat(mapping, repeat, state_storage, now);
+        }
+        if let Event::Controller(ref action, ActionType::State(ref state_action, _), ..) = event {
+            if !state_storage.is_socd(action) {
+                update_state_info(action, state_action, state_storage, now);
+            }
+        }
+        if let (
+            &RawType::Composite(ref composite),
+            &Event::Controller(ref action, ActionType::Range(value), ..),
+        ) = (&mapping.raw_type, &event)
         {
-            update_state_info(action, state_action, state_storage);
+            let target = composite_target(composite, frame_data);
+            update_range_info(action, value, target, state_storage, now);
+        }
+        if let Event::Controller(ref action, ActionType::Range(ref mut value), ..) = event {
+            *value =
+                process_range(&mapping.processors, action, *value, state_storage, env.registry);
+        }
+        Some((mapping, Some(event)))
+    }
+
+    pub fn update(
+        &self,
+        now: Timestamp,
+        state_storage: &mut StateStorage<ACTION>,
+        frame_data: &WindowData,
+        registry: &Registry,
+    ) -> Vec<Event<ACTION, ID>> {
+        let mut events = self.release_impulses(now, state_storage, frame_data);
+        let ramped = self.mappings
+            .iter()
+            .filter_map(|m| {
+                let ramp = match m.raw_type {
+                    RawType::Composite(ref composite) => composite.ramp()?,
+                    _ => return None,
+                };
+                let info = state_storage.ranges.get(&m.action)?.clone();
+                if info.value == info.target {
+                    return None;
+                }
+                let value = ramp_range(info.value, info.target, ramp, now - info.time);
+                state_storage.ranges.insert(
+                    m.action.clone(),
+                    RangeInfo {
+                        value,
+                        target: info.target,
+                        time: now,
+                    },
+                );
+                let value = process_range(&m.processors, &m.action, value, state_storage, registry);
+                Some(Event::Controller(
+                    m.action.clone(),
+                    ActionType::Range(value),
+                    Vec::default(),
+                    now,
+                ))
+            })
+            .collect::<Vec<_>>();
+        events.extend(ramped);
+        events
+    }
+
+    fn release_impulses(
+        &self,
+        now: Timestamp,
+        state_storage: &mut StateStorage<ACTION>,
+        frame_data: &WindowData,
+    ) -> Vec<Event<ACTION, ID>> {
+        let mut events = Vec::default();
+        for mapping in self.mappings.iter() {
+            let idle = match mapping.raw_type {
+                RawType::Motion => frame_data.motion.is_none(),
+                RawType::Scroll => frame_data.scroll.is_none(),
+                _ => false,
+            };
+            if !idle || !state_storage.latched.contains_key(&mapping.handle) {
+                continue;
+            }
+            latch(mapping, &RawState::Release, state_storage);
+            if state_storage.masked.contains(&mapping.action)
+                || !check_state(&mapping.state, &RawState::Release)
+            {
+                continue;
+            }
+            let action_type = match mapping.mapped_type {
+                Some(MappedType::Action) => ActionType::Action,
+                Some(MappedType::State) => ActionType::State(
+                    StateAction::Deactivated,
+                    state_duration(&mapping.action, state_storage, now),
+                ),
+                _ => continue,
+            };
+            let action = mapping.action.clone();
+            let mut event = Event::Controller(action, action_type, Vec::default(), now);
+            if mapping.toggle {
+                event = match toggle_event(event, state_storage) {
+                    Some(event) => event,
+                    None => continue,
+                };
+            }
+            event = match limit_event(mapping, event, state_storage, now) {
+                Some(event) => event,
+                None => continue,
+            };
+            match event {
+                Event::Controller(ref action, ActionType::State(ref state_action, _), ..)
+                    if state_storage.is_socd(action) =>
+                {
+                    let changes = state_storage.resolve_socd(action, state_action, now);
+                    events.extend(changes.into_iter().map(|(side, state_action, duration)| {
+                        let action_type = ActionType::State(state_action, duration);
+                        Event::Controller(side, action_type, Vec::default(), now)
+                    }));
+                    continue;
+                }
+                Event::Controller(ref action, ActionType::State(ref state_action, _), ..) => {
+                    update_state_info(action, state_action, state_storage, now)
+                }
+                _ => (),
+            }
+            events.push(event);
+        }
+        events
+    }
+}
+
+impl<ACTION> StateStorage<ACTION>
+where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    pub fn release_inputs(&mut self, input: &Input) {
+        self.holds.retain(|h| !releases(&h.raw_type, input));
+        self.repeats.retain(|r| !releases(&r.raw_type, input));
+    }
+
+    pub fn release(&mut self, action: &ACTION, now: Timestamp) -> Option<StateDuration> {
+        self.toggles.remove(action);
+        self.pressed.remove(action);
+        match self.states.get_mut(action) {
+            Some(ref mut info) if info.active => {
+                info.active = false;
+                info.stop_time = now;
+                Some(now - info.start_time)
+            }
+            _ => None,
+        }
+    }
+
+    // Drops everything a mapping may have left pending for its action.
+    pub fn release_mapping(
+        &mut self,
+        handle: MappingHandle,
+        action: &ACTION,
+        now: Timestamp,
+    ) -> Option<StateDuration> {
+        self.latched.remove(&handle);
+        self.holds.retain(|h| h.action != *action);
+        self.repeats.retain(|r| r.action != *action);
+        self.release(action, now)
+    }
+
+    pub fn release_all(&mut self, now: Timestamp) -> Vec<(ACTION, StateDuration)> {
+        self.holds.clear();
+        self.repeats.clear();
+        self.toggles.clear();
+        self.latched.clear();
+        self.pressed.clear();
+        self.states
+            .iter_mut()
+            .filter(|&(_, ref info)| info.active)
+            .map(|(action, info)| {
+                info.active = false;
+                info.stop_time = now;
+                (action.clone(), now - info.start_time)
+            })
+            .collect()
+    }
+
+    pub fn reset_ranges(&mut self, now: Timestamp) -> Vec<ACTION> {
+        self.smoothed.clear();
+        self.ranges
+            .iter_mut()
+            .filter(|&(_, ref info)| info.value != (0.0, 0.0) || info.target != (0.0, 0.0))
+            .map(|(action, info)| {
+                *info = RangeInfo {
+                    value: (0.0, 0.0),
+                    target: (0.0, 0.0),
+                    time: now,
+                };
+                action.clone()
+            })
+            .collect()
+    }
+
+    pub fn mask(&mut self, masked: Vec<ACTION>, now: Timestamp) -> Vec<(ACTION, StateDuration)> {
+        self.holds.retain(|h| !masked.contains(&h.action));
+        self.repeats.retain(|r| !masked.contains(&r.action));
+        self.latched.retain(|_, action| !masked.contains(action));
+        let mut released = Vec::default();
+        for action in masked.iter() {
+            self.ranges.remove(action);
+            self.smoothed.remove(action);
+            if let Some(duration) = self.release(action, now) {
+                released.push((action.clone(), duration));
+            }
+        }
+        self.masked = masked;
+        released
+    }
+
+    pub fn is_socd(&self, action: &ACTION) -> bool {
+        self.socd
+            .iter()
+            .any(|p| p.first == *action || p.second == *action)
+    }
+
+    pub fn resolve_socd(
+        &mut self,
+        action: &ACTION,
+        state_action: &StateAction,
+        now: Timestamp,
+    ) -> Vec<(ACTION, StateAction, StateDuration)> {
+        match *state_action {
+            StateAction::Deactivated => {
+                self.pressed.remove(action);
+            }
+            _ => if !self.pressed.contains_key(action) {
+                self.press_sequence += 1;
+                self.pressed.insert(action.clone(), self.press_sequence);
+            },
+        }
+        let mut changes = Vec::default();
+        let pairs: Vec<SocdPair<ACTION>> = self.socd
+            .iter()
+            .filter(|p| p.first == *action || p.second == *action)
+            .cloned()
+            .collect();
+        for pair in pairs {
+            let winner = socd_winner(&pair, &self.pressed);
+            for side in vec![&pair.first, &pair.second] {
+                let active = self.is_active(side);
+                let change = match (winner == Some(side), active) {
+                    (true, false) => StateAction::Activated,
+                    (false, true) => StateAction::Deactivated,
+                    (true, true) if side == action => StateAction::Active,
+                    _ => continue,
+                };
+                let duration = state_duration(side, self, now);
+                update_state_info(side, &change, self, now);
+                changes.push((side.clone(), change, duration));
+            }
+        }
+        changes
+    }
+
+    pub fn expire_holds(&mut self, now: f64) -> Vec<ACTION> {
+        let (expired, pending): (Vec<_>, Vec<_>) = self.holds
+            .drain(..)
+            .partition(|h| now - h.start_time >= h.duration);
+        self.holds = pending;
+        expired.into_iter().map(|h| h.action).collect()
+    }
+
+    pub fn expire_repeats(&mut self, now: f64) -> Vec<(ACTION, Timestamp)> {
+        let mut fired = Vec::default();
+        for r in self.repeats.iter_mut().filter(|r| r.next_time <= now) {
+            fired.push((r.action.clone(), r.next_time));
+            r.next_time += r.interval;
+            if r.next_time <= now {
+                r.next_time = now + r.interval;
+            }
+            r.interval = (r.interval * r.repeat.acceleration)
+                .max(r.repeat.min_interval)
+                .max(MIN_REPEAT_INTERVAL);
+        }
+        fired
+    }
+}
+
+fn socd_winner<'a, ACTION>(
+    pair: &'a SocdPair<ACTION>,
+    pressed: &HashMap<ACTION, u64>,
+) -> Option<&'a ACTION>
+where
+    ACTION: Hash + Eq,
+{
+    match (pressed.get(&pair.first), pressed.get(&pair.second)) {
+        (None, None) => None,
+        (Some(_), None) => Some(&pair.first),
+        (None, Some(_)) => Some(&pair.second),
+        (Some(first), Some(second)) => match pair.mode {
+            Socd::Neutral => None,
+            Socd::LastInputWins => Some(if second > first {
+                &pair.second
+            } else {
+                &pair.first
+            }),
+            Socd::FirstInputWins => Some(if second < first {
+                &pair.second
+            } else {
+                &pair.first
+            }),
+            Socd::Priority(ref action) => Some(action),
+        },
+    }
+}
+
+fn toggle_event<ACTION, ID>(
+    event: Event<ACTION, ID>,
+    state_storage: &mut StateStorage<ACTION>,
+) -> Option<Event<ACTION, ID>>
+where
+    ACTION: Hash + Eq + Clone + Debug,
+    ID: Clone + Debug,
+{
+    match event {
+        Event::Controller(action, ActionType::State(state_action, duration), args, now) => {
+            let was_held = state_storage
+                .toggles
+                .insert(action.clone(), state_action != StateAction::Deactivated)
+                .unwrap_or(false);
+            if state_action == StateAction::Deactivated || was_held {
+                return None;
+            }
+            let toggled = if state_storage.is_active(&action) {
+                StateAction::Deactivated
+            } else {
+                StateAction::Activated
+            };
+            Some(Event::Controller(
+                action,
+                ActionType::State(toggled, duration),
+                args,
+                now,
+            ))
         }
+        event => Some(event),
+    }
+}
+
+fn limit_event<ACTION, ID>(
+    mapping: &Mapping<ACTION, ID>,
+    event: Event<ACTION, ID>,
+    state_storage: &mut StateStorage<ACTION>,
+    now: Timestamp,
+) -> Option<Event<ACTION, ID>>
+where
+    ACTION: Hash + Eq + Clone + Debug,
+    ID: Clone + Debug,
+{
+    let limit = match mapping
+        .limit
+        .as_ref()
+        .or_else(|| state_storage.limits.get(&mapping.action))
+    {
+        Some(limit) => limit.clone(),
+        None => return Some(event),
+    };
+    let trigger = match event {
+        Event::Controller(_, ActionType::Action, ..) |
+        Event::Controller(_, ActionType::State(StateAction::Activated, _), ..) => true,
+        Event::Controller(ref action, ActionType::State(StateAction::Deactivated, _), ..) => {
+            if !state_storage.is_active(action) {
+                return None;
+            }
+            false
+        }
+        _ => return Some(event),
+    };
+    let info = state_storage
+        .limit_info
+        .entry(mapping.action.clone())
+        .or_insert_with(LimitInfo::default);
+    if !trigger {
+        info.last_edge = now;
+        return Some(event);
+    }
+    if now - info.last_edge < limit.debounce {
+        return None;
+    }
+    info.last_edge = now;
+    let mut remaining = info.last_trigger + limit.cooldown - now;
+    if let Some((count, window)) = limit.max_rate {
+        info.recent.retain(|t| now - t < window);
+        if info.recent.len() >= count as usize {
+            remaining = remaining.max(info.recent[0] + window - now);
+        }
+    }
+    if remaining > 0.0 {
+        return match (limit.feedback, event) {
+            (true, Event::Controller(action, _, args, now)) => Some(Event::Controller(
+                action,
+                ActionType::Blocked(remaining),
+                args,
+                now,
+            )),
+            _ => None,
+        };
+    }
+    info.last_trigger = now;
+    info.recent.push(now);
+    Some(event)
+}
+
+fn start_hold<ACTION, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    duration: f64,
+    state_storage: &mut StateStorage<ACTION>,
+    now: Timestamp,
+) where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    if state_storage.holds.iter().any(|h| h.action == mapping.action) {
+        return;
+    }
+    state_storage.holds.push(PendingHold {
+        action: mapping.action.clone(),
+        raw_type: mapping.raw_type.clone(),
+        start_time: now,
+        duration,
+    });
+}
+
+fn start_repeat<ACTION, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    repeat: &Repeat,
+    state_storage: &mut StateStorage<ACTION>,
+    now: Timestamp,
+) where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    if repeat.interval <= 0.0 || state_storage.repeats.iter().any(|r| r.action == mapping.action)
+    {
+        return;
+    }
+    state_storage.repeats.push(PendingRepeat {
+        action: mapping.action.clone(),
+        raw_type: mapping.raw_type.clone(),
+        repeat: repeat.clone(),
+        next_time: now + repeat.delay,
+        interval: repeat.interval,
+    });
+}
+
+impl WindowData {
+    pub fn update(&mut self, raw_input: &winit::Event) {
         match *raw_input {
             winit::Event::WindowEvent {
                 event: winit::WindowEvent::MouseMoved { position, .. },
                 ..
             } => {
-                frame_data.cursor_position = Some((
-                    position.0 / frame_data.size.0,
-                    position.1 / frame_data.size.1,
-                ))
+                self.motion = Some(accumulate(self.motion, raw_range(raw_input, self)));
+                self.cursor_position =
+                    Some((position.0 / self.size.0, position.1 / self.size.1));
+            }
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::MouseWheel { .. },
+                ..
+            } => {
+                self.scroll = Some(accumulate(self.scroll, raw_range(raw_input, self)));
             }
             winit::Event::WindowEvent {
                 event: winit::WindowEvent::Resized(width, height),
                 ..
             } => {
-                frame_data.size = (width as f64, height as f64);
+                self.size = (width as f64, height as f64);
             }
             _ => (),
         };
-        event
+    }
+
+    pub fn update_held(&mut self, raw_input: &winit::Event) {
+        self.repeated = false;
+        match *raw_input {
+            winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::KeyboardInput {
+                        input:
+                            winit::KeyboardInput {
+                                state,
+                                virtual_keycode: Some(ref virtual_keycode),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let keycode: KeyCode = virtual_keycode.into();
+                let pressed = state == winit::ElementState::Pressed;
+                self.repeated = pressed && self.held_keys.contains(&keycode);
+                self.held_keys.retain(|k| *k != keycode);
+                if pressed {
+                    self.held_keys.push(keycode);
+                }
+            }
+            winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::MouseInput {
+                        state, ref button, ..
+                    },
+                ..
+            } => {
+                let button: MouseButton = button.into();
+                self.held_buttons.retain(|b| *b != button);
+                if state == winit::ElementState::Pressed {
+                    self.held_buttons.push(button);
+                }
+            }
+            _ => (),
+        };
+    }
+
+    pub fn reset_impulses(&mut self, now: Timestamp) {
+        self.motion = None;
+        self.scroll = None;
+        self.update_interval = now - self.last_update;
+        self.last_update = now;
+    }
+
+    // Time over which motion has accumulated, falling back to the last update's length.
+    pub fn impulse_interval(&self) -> Option<f64> {
+        Some(self.time - self.last_update)
+            .filter(|dt| *dt > 0.0)
+            .or(Some(self.update_interval))
+            .filter(|dt| *dt > 0.0)
+    }
+
+    pub fn is_held(&self, raw_type: &RawType) -> bool {
+        match *raw_type {
+            RawType::Key(ref keycode) => self.held_keys.contains(keycode),
+            RawType::Button(ref button) => self.held_buttons.contains(button),
+            _ => false,
+        }
     }
 }
 
-fn process_internal<ACTION, ID>(
-    context: &Context<ACTION, ID>,
-    raw_input: &winit::Event,
+fn skip(trace: Option<&mut Vec<MappingTrace>>, reason: MatchResult) {
+    if let Some(mapping_trace) = trace.and_then(|t| t.last_mut()) {
+        mapping_trace.result = reason;
+    }
+}
+
+fn mapping_event<ACTION, ID>(
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
+    context_id: &ID,
+    frame_data: &WindowData,
     state_storage: &StateStorage<ACTION>,
-    frame_data: &mut WindowData,
+    now: Timestamp,
 ) -> Option<Event<ACTION, ID>>
 where
     ACTION: Hash + Eq + Clone + Debug,
     ID: Clone + Debug,
 {
-    context
-        .mappings
-        .iter()
-        .filter(|m| check_mapping(m, raw_input, state_storage))
-        .filter_map(|m| {
-            m.mapped_type.as_ref().map(|t| match *t {
-                MappedType::Action => as_action(m, raw_input, &context.id, frame_data),
-                MappedType::Range => as_range(m, raw_input, &context.id, frame_data),
-                MappedType::State => as_state(m, raw_input, &context.id, frame_data, state_storage),
-            })
-        })
-        .next()
+    Some(match *mapping.mapped_type.as_ref()? {
+        MappedType::Action => as_action(mapping, input, context_id, frame_data, now),
+        MappedType::Range => as_range(mapping, input, context_id, frame_data, state_storage, now),
+        MappedType::State => as_state(mapping, input, context_id, frame_data, state_storage, now),
+    })
 }
 
 fn as_state<ACTION, ID>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
     context_id: &ID,
     frame_data: &WindowData,
     state_storage: &StateStorage<ACTION>,
+    now: Timestamp,
 ) -> Event<ACTION, ID>
 where
     ACTION: Hash + Eq + Clone + Debug,
     ID: Clone + Debug,
 {
+    let raw_state = match mapping.threshold {
+        Some(_) => {
+            threshold_state(mapping, input, frame_data, state_storage).unwrap_or(RawState::Release)
+        }
+        None => get_raw_state(input),
+    };
     Event::Controller(
         mapping.action.clone(),
         ActionType::State(
-            state_action(&mapping.action, &get_raw_state(raw_input), state_storage),
-            state_duration(&mapping.action, state_storage),
+            state_action(&mapping.action, &raw_state, state_storage),
+            state_duration(&mapping.action, state_storage, now),
         ),
-        arguments(&mapping.action_args, raw_input, context_id, frame_data),
+        arguments(&mapping.action_args, input, context_id, frame_data),
+        now,
     )
 }
 
-fn get_raw_state(raw_input: &winit::Event) -> winit::ElementState {
+fn get_raw_state(input: &Input) -> RawState {
     use winit::{Event, KeyboardInput, WindowEvent};
+    let raw_input = match *input {
+        Input::Raw(raw_input) => raw_input,
+        Input::Touch(ref gesture) => return gesture.state.clone(),
+        Input::Virtual(ref virtual_input) => return virtual_input.state.clone(),
+        Input::Gamepad(GamepadEvent::Button(_, ref state)) => return state.clone(),
+        Input::Gamepad(GamepadEvent::Axis(..)) => return RawState::Release,
+    };
     match *raw_input {
         Event::WindowEvent {
             event:
@@ -107,21 +780,21 @@ fn get_raw_state(raw_input: &winit::Event) -> winit::ElementState {
         Event::WindowEvent {
             event: WindowEvent::MouseInput { state, .. },
             ..
-        } => state.clone(),
-        _ => winit::ElementState::Released,
+        } => state.into(),
+        _ => RawState::Release,
     }
 }
 
 fn state_action<ACTION>(
     c_action: &ACTION,
-    raw_input: &winit::ElementState,
+    raw_state: &RawState,
     state_storage: &StateStorage<ACTION>,
 ) -> StateAction
 where
     ACTION: Hash + Eq + Clone + Debug,
 {
-    match *raw_input {
-        winit::ElementState::Pressed => if state_storage
+    match *raw_state {
+        RawState::Press => if state_storage
             .states
             .get(c_action)
             .map(|i| i.active)
@@ -131,15 +804,18 @@ where
         } else {
             StateAction::Activated
         },
-        winit::ElementState::Released => StateAction::Deactivated,
+        RawState::Release => StateAction::Deactivated,
     }
 }
 
-fn state_duration<ACTION>(c_action: &ACTION, state_storage: &StateStorage<ACTION>) -> StateDuration
+fn state_duration<ACTION>(
+    c_action: &ACTION,
+    state_storage: &StateStorage<ACTION>,
+    now: Timestamp,
+) -> StateDuration
 where
     ACTION: Hash + Eq + Clone + Debug,
 {
-    let now = time::precise_time_ns() as f64 / 1000000000.0;
     match state_storage.states.get(c_action) {
         Some(info) => if info.active && info.start_time <= now {
             now - info.start_time
@@ -154,10 +830,10 @@ fn update_state_info<ACTION>(
     c_action: &ACTION,
     state_action: &StateAction,
     state_storage: &mut StateStorage<ACTION>,
+    now: Timestamp,
 ) where
     ACTION: Hash + Eq + Clone + Debug,
 {
-    let now = time::precise_time_ns() as f64 / 1000000000.;
     match *state_action {
         StateAction::Active | StateAction::Activated => {
             let add = match state_storage.states.get_mut(c_action) {
@@ -188,7 +864,275 @@ fn update_state_info<ACTION>(
     };
 }
 
-fn range_diff(raw_input: &winit::Event, frame_data: &mut WindowData) -> RangeDiff {
+fn update_range_info<ACTION>(
+    c_action: &ACTION,
+    value: RangeDiff,
+    target: RangeDiff,
+    state_storage: &mut StateStorage<ACTION>,
+    now: Timestamp,
+) where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    state_storage.ranges.insert(
+        c_action.clone(),
+        RangeInfo {
+            value,
+            target,
+            time: now,
+        },
+    );
+}
+
+fn composite_target(composite: &Composite, frame_data: &WindowData) -> RangeDiff {
+    let held = |raw_type: &RawType| if frame_data.is_held(raw_type) {
+        1.0
+    } else {
+        0.0
+    };
+    match *composite {
+        Composite::Axis {
+            ref negative,
+            ref positive,
+            ..
+        } => (held(positive) - held(negative), 0.0),
+        Composite::Vector {
+            ref up,
+            ref down,
+            ref left,
+            ref right,
+            ..
+        } => {
+            let (x, y) = (held(right) - held(left), held(down) - held(up));
+            let length = (x * x + y * y).sqrt();
+            if length > 1.0 {
+                (x / length, y / length)
+            } else {
+                (x, y)
+            }
+        }
+    }
+}
+
+fn composite_value<ACTION>(
+    c_action: &ACTION,
+    composite: &Composite,
+    frame_data: &WindowData,
+    state_storage: &StateStorage<ACTION>,
+    now: Timestamp,
+) -> RangeDiff
+where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    let target = composite_target(composite, frame_data);
+    let ramp = match composite.ramp() {
+        Some(ramp) => ramp,
+        None => return target,
+    };
+    match state_storage.ranges.get(c_action) {
+        Some(info) => ramp_range(info.value, target, ramp, now - info.time),
+        None => (0.0, 0.0),
+    }
+}
+
+fn ramp_range(value: RangeDiff, target: RangeDiff, ramp: &Ramp, dt: f64) -> RangeDiff {
+    (
+        ramp_axis(value.0, target.0, ramp, dt),
+        ramp_axis(value.1, target.1, ramp, dt),
+    )
+}
+
+fn ramp_axis(value: f64, target: f64, ramp: &Ramp, dt: f64) -> f64 {
+    let duration = if target.abs() > value.abs() && target * value >= 0.0 {
+        ramp.rise
+    } else {
+        ramp.fall
+    };
+    let step = if duration > 0.0 {
+        dt.max(0.0) / duration
+    } else {
+        return target;
+    };
+    if (target - value).abs() <= step {
+        target
+    } else if target > value {
+        value + step
+    } else {
+        value - step
+    }
+}
+
+fn process_range<ACTION>(
+    processors: &Vec<Processor>,
+    c_action: &ACTION,
+    value: RangeDiff,
+    state_storage: &mut StateStorage<ACTION>,
+    registry: &Registry,
+) -> RangeDiff
+where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    processors.iter().fold(value, |(x, y), processor| match *processor {
+        Processor::DeadZone(dead_zone) => {
+            let length = (x * x + y * y).sqrt();
+            if length <= dead_zone {
+                (0.0, 0.0)
+            } else if dead_zone > 0.0 && dead_zone < 1.0 {
+                let scale = (length - dead_zone) / (1.0 - dead_zone) / length;
+                (x * scale, y * scale)
+            } else {
+                (x, y)
+            }
+        }
+        Processor::Scale(sx, sy) => (x * sx, y * sy),
+        Processor::InvertX => (-x, y),
+        Processor::InvertY => (x, -y),
+        Processor::Curve(exponent) => (
+            x.signum() * x.abs().powf(exponent),
+            y.signum() * y.abs().powf(exponent),
+        ),
+        Processor::Smooth(factor) => {
+            let smoothed = match state_storage.smoothed.get(c_action) {
+                Some(&(px, py)) => (px + (x - px) * factor, py + (y - py) * factor),
+                None => (x, y),
+            };
+            state_storage
+                .smoothed
+                .insert(c_action.clone(), smoothed);
+            smoothed
+        }
+        Processor::Custom(ref name) => match registry.processor(name) {
+            Some(p) => p.process((x, y)),
+            None => {
+                warn!("No range processor registered as {:?}", name);
+                (x, y)
+            }
+        },
+    })
+}
+
+fn latch<ACTION, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    edge: &RawState,
+    state_storage: &mut StateStorage<ACTION>,
+) where
+    ACTION: Hash + Eq + Clone + Debug,
+{
+    match *edge {
+        RawState::Press => {
+            state_storage
+                .latched
+                .insert(mapping.handle, mapping.action.clone());
+        }
+        RawState::Release => {
+            state_storage.latched.remove(&mapping.handle);
+            state_storage.holds.retain(|h| h.action != mapping.action);
+            state_storage.repeats.retain(|r| r.action != mapping.action);
+        }
+    }
+}
+
+fn threshold_state<ACTION, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
+    frame_data: &WindowData,
+    state_storage: &StateStorage<ACTION>,
+) -> Option<RawState>
+where
+    ACTION: Hash + Eq + Clone,
+{
+    let threshold = mapping.threshold.as_ref()?;
+    let value = analog_value(&mapping.raw_type, input, frame_data)?;
+    let latched = state_storage.latched.contains_key(&mapping.handle);
+    threshold_edge(threshold, value, latched)
+}
+
+fn threshold_edge(threshold: &Threshold, value: f64, latched: bool) -> Option<RawState> {
+    let (pressed, released) = if threshold.press >= threshold.release {
+        (value >= threshold.press, value <= threshold.release)
+    } else {
+        (value <= threshold.press, value >= threshold.release)
+    };
+    if !latched && pressed {
+        Some(RawState::Press)
+    } else if latched && released {
+        Some(RawState::Release)
+    } else {
+        None
+    }
+}
+
+// Motion and scroll are measured over everything that arrived since the last update.
+fn analog_value(raw_type: &RawType, input: &Input, frame_data: &WindowData) -> Option<f64> {
+    if let (&RawType::GamepadAxis(ref config_axis), &Input::Gamepad(ref event)) =
+        (raw_type, input)
+    {
+        return match *event {
+            GamepadEvent::Axis(ref axis, value) if axis == config_axis => Some(value),
+            _ => None,
+        };
+    }
+    let raw_input = input.raw()?;
+    match (raw_type, raw_input) {
+        (
+            &RawType::Axis(config_axis),
+            &winit::Event::WindowEvent {
+                event: winit::WindowEvent::AxisMotion { axis, value, .. },
+                ..
+            },
+        ) if config_axis == axis =>
+        {
+            Some(value)
+        }
+        (
+            &RawType::Motion,
+            &winit::Event::WindowEvent {
+                event: winit::WindowEvent::MouseMoved { .. },
+                ..
+            },
+        ) => {
+            let (x, y) = accumulate(frame_data.motion, raw_range(raw_input, frame_data));
+            let speed = frame_data
+                .impulse_interval()
+                .map(|dt| (x * x + y * y).sqrt() / dt);
+            Some(speed.unwrap_or(0.0))
+        }
+        (
+            &RawType::Scroll,
+            &winit::Event::WindowEvent {
+                event: winit::WindowEvent::MouseWheel { .. },
+                ..
+            },
+        ) => {
+            let (x, y) = accumulate(frame_data.scroll, raw_range(raw_input, frame_data));
+            Some((x * x + y * y).sqrt())
+        }
+        (
+            &RawType::Pressure,
+            &winit::Event::WindowEvent {
+                event: winit::WindowEvent::TouchpadPressure { pressure, .. },
+                ..
+            },
+        ) => Some(pressure as f64),
+        _ => None,
+    }
+}
+
+fn accumulate(total: Option<RangeDiff>, delta: RangeDiff) -> RangeDiff {
+    let (x, y) = total.unwrap_or((0.0, 0.0));
+    (x + delta.0, y + delta.1)
+}
+
+fn range_diff(input: &Input, frame_data: &WindowData) -> RangeDiff {
+    match *input {
+        Input::Raw(raw_input) => raw_range(raw_input, frame_data),
+        Input::Touch(ref gesture) => gesture.delta,
+        Input::Virtual(ref virtual_input) => virtual_input.value,
+        Input::Gamepad(GamepadEvent::Axis(_, value)) => (value, 0.0),
+        Input::Gamepad(GamepadEvent::Button(..)) => (0.0, 0.0),
+    }
+}
+
+fn raw_range(raw_input: &winit::Event, frame_data: &WindowData) -> RangeDiff {
     match *raw_input {
         winit::Event::WindowEvent {
             event: winit::WindowEvent::MouseMoved { position, .. },
@@ -201,13 +1145,28 @@ fn range_diff(raw_input: &winit::Event, frame_data: &mut WindowData) -> RangeDif
         } else {
             (0.0, 0.0)
         },
+        winit::Event::WindowEvent {
+            event: winit::WindowEvent::MouseWheel { delta, .. },
+            ..
+        } => match delta {
+            winit::MouseScrollDelta::LineDelta(x, y) |
+            winit::MouseScrollDelta::PixelDelta(x, y) => (x as f64, y as f64),
+        },
+        winit::Event::WindowEvent {
+            event: winit::WindowEvent::AxisMotion { value, .. },
+            ..
+        } => (value, 0.0),
+        winit::Event::WindowEvent {
+            event: winit::WindowEvent::TouchpadPressure { pressure, .. },
+            ..
+        } => (pressure as f64, 0.0),
         _ => (0.0, 0.0),
     }
 }
 
 fn arguments<ID>(
     args: &Vec<ActionArgument>,
-    raw_input: &winit::Event,
+    input: &Input,
     context_id: &ID,
     frame_data: &WindowData,
 ) -> Vec<Argument<ID>>
@@ -216,20 +1175,21 @@ where
 {
     args.iter()
         .filter_map(|arg| match arg {
-            &ActionArgument::KeyCode => get_keycode(raw_input),
-            &ActionArgument::Value => get_value(raw_input),
-            &ActionArgument::Action => get_action(raw_input),
-            &ActionArgument::CursorPosition => get_cursor_position(frame_data),
+            &ActionArgument::KeyCode => input.raw().and_then(get_keycode),
+            &ActionArgument::Value => input.raw().and_then(get_value),
+            &ActionArgument::Action => get_action(input),
+            &ActionArgument::CursorPosition => get_cursor_position(input, frame_data),
             &ActionArgument::ContextId => Some(Argument::ContextId(context_id.clone())),
         })
         .collect()
 }
 
 fn as_action<ACTION, ID>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
     context_id: &ID,
     frame_data: &WindowData,
+    now: Timestamp,
 ) -> Event<ACTION, ID>
 where
     ACTION: Debug + Clone,
@@ -238,46 +1198,261 @@ where
     Event::Controller(
         mapping.action.clone(),
         ActionType::Action,
-        arguments(&mapping.action_args, raw_input, context_id, frame_data),
+        arguments(&mapping.action_args, input, context_id, frame_data),
+        now,
     )
 }
 
 fn as_range<ACTION, ID>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
     context_id: &ID,
-    frame_data: &mut WindowData,
+    frame_data: &WindowData,
+    state_storage: &StateStorage<ACTION>,
+    now: Timestamp,
 ) -> Event<ACTION, ID>
 where
-    ACTION: Debug + Clone,
+    ACTION: Hash + Eq + Clone + Debug,
     ID: Debug + Clone,
 {
+    let value = match mapping.raw_type {
+        RawType::Composite(ref composite) => {
+            composite_value(&mapping.action, composite, frame_data, state_storage, now)
+        }
+        _ => range_diff(input, frame_data),
+    };
     Event::Controller(
         mapping.action.clone(),
-        ActionType::Range(range_diff(raw_input, frame_data)),
-        arguments(&mapping.action_args, raw_input, context_id, frame_data),
+        ActionType::Range(value),
+        arguments(&mapping.action_args, input, context_id, frame_data),
+        now,
     )
 }
 
-fn check_mapping<ACTION: Clone + Hash + Eq>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
+// The state of a matching input, or None if the raw type doesn't carry one.
+type InputMatch = Result<Option<RawState>, MatchResult>;
+
+fn check_mapping<ACTION: Clone + Hash + Eq, ID: Clone + Eq + Debug>(
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
     state_storage: &StateStorage<ACTION>,
-) -> bool {
-    match mapping.raw_type {
-        RawType::Button(ref button) => check_button(button, mapping, raw_input, state_storage),
-        RawType::Key(ref keycode) => check_key(keycode, mapping, raw_input, state_storage),
-        RawType::Motion => check_motion(mapping, raw_input, state_storage),
-        RawType::Char => check_char(mapping, raw_input, state_storage),
+    frame_data: &WindowData,
+    env: &Environment<ID>,
+) -> MatchResult {
+    if state_storage.masked.contains(&mapping.action) {
+        return MatchResult::Masked;
+    }
+    let raw_state = match check_input(mapping, input, frame_data, state_storage) {
+        Ok(raw_state) => raw_state,
+        Err(result) => return result,
+    };
+    if !check_state_active(&mapping.condition, state_storage, env) {
+        return MatchResult::ConditionFailed;
+    }
+    if let Some(ref raw_state) = raw_state {
+        if !check_state(&mapping.state, raw_state) {
+            return MatchResult::StateMismatch;
+        }
+    }
+    if frame_data.repeated && (mapping.suppress_repeat || mapping.repeat.is_some()) {
+        return MatchResult::RepeatSuppressed;
+    }
+    match mapping.mapped_type {
+        Some(_) => MatchResult::Matched,
+        None => MatchResult::Unmapped,
     }
 }
 
-fn check_button<ACTION: Clone + Hash + Eq>(
-    config_button: &MouseButton,
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
+fn check_input<ACTION: Clone + Hash + Eq, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    input: &Input,
+    frame_data: &WindowData,
     state_storage: &StateStorage<ACTION>,
-) -> bool {
+) -> InputMatch {
+    if mapping.threshold.is_some() {
+        if analog_value(&mapping.raw_type, input, frame_data).is_none() {
+            return Err(MatchResult::RawTypeMismatch);
+        }
+        return match threshold_state(mapping, input, frame_data, state_storage) {
+            Some(edge) => Ok(Some(edge)),
+            None => Err(MatchResult::ThresholdNotCrossed),
+        };
+    }
+    match *input {
+        Input::Raw(raw_input) => check_raw(mapping, raw_input, frame_data),
+        Input::Touch(ref gesture) => check_touch(mapping, gesture),
+        Input::Virtual(ref virtual_input) => check_virtual(mapping, virtual_input),
+        Input::Gamepad(ref gamepad_event) => check_gamepad(mapping, gamepad_event),
+    }
+}
+
+fn check_raw<ACTION: Clone + Hash + Eq, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    raw_input: &winit::Event,
+    frame_data: &WindowData,
+) -> InputMatch {
+    let matched = match mapping.raw_type {
+        RawType::Button(ref button) => return check_button(button, raw_input),
+        RawType::Key(ref keycode) => return check_key(keycode, raw_input),
+        RawType::Motion => check_motion(raw_input),
+        RawType::Char => check_char(raw_input),
+        RawType::Composite(ref composite) => check_composite(composite, raw_input),
+        RawType::Axis(_) | RawType::Scroll | RawType::Pressure => {
+            analog_value(&mapping.raw_type, &Input::Raw(raw_input), frame_data).is_some()
+        }
+        RawType::Touch(_) |
+        RawType::Virtual(_) |
+        RawType::GamepadButton(_) |
+        RawType::GamepadAxis(_) => false,
+    };
+    if matched {
+        Ok(None)
+    } else {
+        Err(MatchResult::RawTypeMismatch)
+    }
+}
+
+fn check_composite(composite: &Composite, raw_input: &winit::Event) -> bool {
+    composite
+        .components()
+        .iter()
+        .any(|raw_type| check_component(raw_type, raw_input))
+}
+
+fn raw_type(raw_input: &winit::Event) -> Option<RawType> {
+    use winit::{Event, KeyboardInput, WindowEvent};
+    match *raw_input {
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(ref virtual_keycode),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => Some(RawType::Key(virtual_keycode.into())),
+        Event::WindowEvent {
+            event: WindowEvent::MouseInput { ref button, .. },
+            ..
+        } => Some(RawType::Button(button.into())),
+        Event::WindowEvent {
+            event: WindowEvent::MouseMoved { .. },
+            ..
+        } => Some(RawType::Motion),
+        Event::WindowEvent {
+            event: WindowEvent::ReceivedCharacter(_),
+            ..
+        } => Some(RawType::Char),
+        Event::WindowEvent {
+            event: WindowEvent::MouseWheel { .. },
+            ..
+        } => Some(RawType::Scroll),
+        Event::WindowEvent {
+            event: WindowEvent::AxisMotion { axis, .. },
+            ..
+        } => Some(RawType::Axis(axis)),
+        Event::WindowEvent {
+            event: WindowEvent::TouchpadPressure { .. },
+            ..
+        } => Some(RawType::Pressure),
+        _ => None,
+    }
+}
+
+pub fn releases(raw_type: &RawType, input: &Input) -> bool {
+    if get_raw_state(input) != RawState::Release {
+        return false;
+    }
+    match (raw_type, input) {
+        (_, &Input::Raw(raw_input)) => check_component(raw_type, raw_input),
+        (&RawType::Touch(ref config_gesture), &Input::Touch(ref gesture)) => {
+            *config_gesture == gesture.gesture
+        }
+        (&RawType::Virtual(ref name), &Input::Virtual(ref virtual_input)) => {
+            *name == virtual_input.name
+        }
+        (
+            &RawType::GamepadButton(ref config_button),
+            &Input::Gamepad(GamepadEvent::Button(ref button, _)),
+        ) => config_button == button,
+        _ => false,
+    }
+}
+
+fn check_component(raw_type: &RawType, raw_input: &winit::Event) -> bool {
+    match (raw_type, raw_input) {
+        (
+            &RawType::Key(ref keycode),
+            &winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::KeyboardInput {
+                        input:
+                            winit::KeyboardInput {
+                                virtual_keycode: Some(ref virtual_keycode),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            },
+        ) => check_keycode(keycode, virtual_keycode),
+        (
+            &RawType::Button(ref config_button),
+            &winit::Event::WindowEvent {
+                event: winit::WindowEvent::MouseInput { ref button, .. },
+                ..
+            },
+        ) => check_button_id(config_button, button),
+        _ => false,
+    }
+}
+
+fn check_touch<ACTION: Clone + Hash + Eq, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    gesture: &Gesture,
+) -> InputMatch {
+    match mapping.raw_type {
+        RawType::Touch(ref config_gesture) if *config_gesture == gesture.gesture => {
+            Ok(Some(gesture.state.clone()))
+        }
+        _ => Err(MatchResult::RawTypeMismatch),
+    }
+}
+
+fn check_virtual<ACTION: Clone + Hash + Eq, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    virtual_input: &VirtualInput,
+) -> InputMatch {
+    match mapping.raw_type {
+        RawType::Virtual(ref name) if *name == virtual_input.name => {
+            Ok(Some(virtual_input.state.clone()))
+        }
+        _ => Err(MatchResult::RawTypeMismatch),
+    }
+}
+
+fn check_gamepad<ACTION: Clone + Hash + Eq, ID: Clone>(
+    mapping: &Mapping<ACTION, ID>,
+    gamepad_event: &GamepadEvent,
+) -> InputMatch {
+    match (&mapping.raw_type, gamepad_event) {
+        (
+            &RawType::GamepadButton(ref config_button),
+            &GamepadEvent::Button(ref button, ref state),
+        ) if config_button == button => Ok(Some(state.clone())),
+        (&RawType::GamepadAxis(ref config_axis), &GamepadEvent::Axis(ref axis, _))
+            if config_axis == axis =>
+        {
+            Ok(None)
+        }
+        _ => Err(MatchResult::RawTypeMismatch),
+    }
+}
+
+fn check_button(config_button: &MouseButton, raw_input: &winit::Event) -> InputMatch {
     match *raw_input {
         winit::Event::WindowEvent {
             event:
@@ -287,20 +1462,15 @@ fn check_button<ACTION: Clone + Hash + Eq>(
                     ..
                 },
             ..
-        } => {
-            check_button_id(config_button, button) && check_state(&mapping.state, state)
-                && check_state_active(&mapping.state_active, state_storage)
+        } if check_button_id(config_button, button) =>
+        {
+            Ok(Some(state.clone().into()))
         }
-        _ => false,
+        _ => Err(MatchResult::RawTypeMismatch),
     }
 }
 
-fn check_key<ACTION: Clone + Hash + Eq>(
-    keycode: &KeyCode,
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
-    state_storage: &StateStorage<ACTION>,
-) -> bool {
+fn check_key(keycode: &KeyCode, raw_input: &winit::Event) -> InputMatch {
     match *raw_input {
         winit::Event::WindowEvent {
             event:
@@ -308,59 +1478,76 @@ fn check_key<ACTION: Clone + Hash + Eq>(
                     input:
                         winit::KeyboardInput {
                             ref state,
-                            ref virtual_keycode,
+                            virtual_keycode: Some(ref virtual_keycode),
                             ..
                         },
                     ..
                 },
             ..
-        } => {
-            check_keycode(keycode, virtual_keycode.as_ref().unwrap())
-                && check_state(&mapping.state, state)
-                && check_state_active(&mapping.state_active, state_storage)
+        } if check_keycode(keycode, virtual_keycode) =>
+        {
+            Ok(Some(state.clone().into()))
         }
-        _ => false,
+        _ => Err(MatchResult::RawTypeMismatch),
     }
 }
 
-fn check_motion<ACTION: Clone + Hash + Eq>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
-    state_storage: &StateStorage<ACTION>,
-) -> bool {
+fn check_motion(raw_input: &winit::Event) -> bool {
     match *raw_input {
         winit::Event::WindowEvent {
             event: winit::WindowEvent::MouseMoved { .. },
             ..
-        } => check_state_active(&mapping.state_active, state_storage),
+        } => true,
         _ => false,
     }
 }
 
-fn check_char<ACTION: Clone + Hash + Eq>(
-    mapping: &Mapping<ACTION>,
-    raw_input: &winit::Event,
-    state_storage: &StateStorage<ACTION>,
-) -> bool {
+fn check_char(raw_input: &winit::Event) -> bool {
     match *raw_input {
         winit::Event::WindowEvent {
             event: winit::WindowEvent::ReceivedCharacter(_),
             ..
-        } => check_state_active(&mapping.state_active, state_storage),
+        } => true,
         _ => false,
     }
 }
 
-fn check_state_active<ACTION: Clone + Hash + Eq>(
-    state_active: &Option<ACTION>,
+fn check_state_active<ACTION: Clone + Hash + Eq, ID: Clone + Eq + Debug>(
+    condition: &Option<Condition<ACTION, ID>>,
     state_storage: &StateStorage<ACTION>,
+    env: &Environment<ID>,
 ) -> bool {
-    state_active
+    condition
         .as_ref()
-        .map(|state| state_storage.is_active(state))
+        .map(|c| check_condition(c, state_storage, env))
         .unwrap_or(true)
 }
 
+fn check_condition<ACTION: Clone + Hash + Eq, ID: Clone + Eq + Debug>(
+    condition: &Condition<ACTION, ID>,
+    state_storage: &StateStorage<ACTION>,
+    env: &Environment<ID>,
+) -> bool {
+    match *condition {
+        Condition::State(ref state) => state_storage.is_active(state),
+        Condition::Context(ref id) => env.active_contexts.iter().any(|ac| ac.context_id == *id),
+        Condition::All(ref conditions) => conditions
+            .iter()
+            .all(|c| check_condition(c, state_storage, env)),
+        Condition::Any(ref conditions) => conditions
+            .iter()
+            .any(|c| check_condition(c, state_storage, env)),
+        Condition::Not(ref condition) => !check_condition(condition, state_storage, env),
+        Condition::Custom(ref name) => match env.registry.predicate(name) {
+            Some(p) => p.check(env.user),
+            None => {
+                warn!("No predicate registered as {:?}", name);
+                false
+            }
+        },
+    }
+}
+
 fn check_button_id(config_button: &MouseButton, raw_button_id: &winit::MouseButton) -> bool {
     *config_button == raw_button_id.into()
 }
@@ -369,10 +1556,10 @@ fn check_keycode(config_keycode: &KeyCode, raw_keycode: &winit::VirtualKeyCode)
     *config_keycode == raw_keycode.into()
 }
 
-fn check_state(config_action: &Option<RawState>, raw_action: &winit::ElementState) -> bool {
-    match (config_action, *raw_action) {
-        (&Some(RawState::Press), winit::ElementState::Pressed) => true,
-        (&Some(RawState::Release), winit::ElementState::Released) => true,
+fn check_state(config_action: &Option<RawState>, raw_action: &RawState) -> bool {
+    match (config_action, raw_action) {
+        (&Some(RawState::Press), &RawState::Press) => true,
+        (&Some(RawState::Release), &RawState::Release) => true,
         (&None, _) => true,
         _ => false,
     }
@@ -411,10 +1598,21 @@ where
     }
 }
 
-fn get_action<ID>(raw_input: &winit::Event) -> Option<Argument<ID>>
+fn get_action<ID>(input: &Input) -> Option<Argument<ID>>
 where
     ID: Debug + Clone,
 {
+    let raw_input = match *input {
+        Input::Raw(raw_input) => raw_input,
+        Input::Touch(ref gesture) => return Some(Argument::Action(gesture.state.clone())),
+        Input::Virtual(ref virtual_input) => {
+            return Some(Argument::Action(virtual_input.state.clone()))
+        }
+        Input::Gamepad(GamepadEvent::Button(_, ref state)) => {
+            return Some(Argument::Action(state.clone()))
+        }
+        Input::Gamepad(GamepadEvent::Axis(..)) => return None,
+    };
     match *raw_input {
         winit::Event::WindowEvent { ref event, .. } => match *event {
             winit::WindowEvent::KeyboardInput {
@@ -430,12 +1628,260 @@ where
     }
 }
 
-fn get_cursor_position<ID>(frame_data: &WindowData) -> Option<Argument<ID>>
+fn get_cursor_position<ID>(input: &Input, frame_data: &WindowData) -> Option<Argument<ID>>
 where
     ID: Debug + Clone,
 {
+    match *input {
+        Input::Touch(ref gesture) => {
+            return Some(Argument::CursorPosition(gesture.position.0, gesture.position.1))
+        }
+        Input::Virtual(ref virtual_input) => {
+            return Some(Argument::CursorPosition(
+                virtual_input.position.0,
+                virtual_input.position.1,
+            ))
+        }
+        Input::Raw(_) | Input::Gamepad(_) => (),
+    }
     match frame_data.cursor_position {
         Some((x, y)) => Some(Argument::CursorPosition(x, y)),
         None => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Action {
+        Fire,
+        Guard,
+        Left,
+        Right,
+    }
+
+    impl ActionMetadata for Action {
+        fn mapped_type(&self) -> MappedType {
+            match *self {
+                Action::Fire => MappedType::Action,
+                Action::Guard | Action::Left | Action::Right => MappedType::State,
+            }
+        }
+
+        fn args(&self) -> Vec<ActionArgument> {
+            Vec::default()
+        }
+    }
+
+    fn state(action: Action, state_action: StateAction) -> Event<Action, u32> {
+        Event::Controller(action, ActionType::State(state_action, 0.0), Vec::default(), 0.0)
+    }
+
+    fn fire() -> Event<Action, u32> {
+        Event::Controller(Action::Fire, ActionType::Action, Vec::default(), 0.0)
+    }
+
+    fn limited(
+        mapping: &Mapping<Action, u32>,
+        event: Event<Action, u32>,
+        state_storage: &mut StateStorage<Action>,
+        now: Timestamp,
+    ) -> Option<ActionType> {
+        let event = limit_event(mapping, event, state_storage, now)?;
+        if let Event::Controller(ref action, ActionType::State(ref state_action, _), ..) = event {
+            update_state_info(action, state_action, state_storage, now);
+        }
+        match event {
+            Event::Controller(_, action_type, ..) => Some(action_type),
+            _ => None,
+        }
+    }
+
+    fn threshold(press: f64, release: f64) -> Threshold {
+        Threshold { press, release }
+    }
+
+    fn socd(mode: Socd<Action>) -> StateStorage<Action> {
+        let mut storage = StateStorage::new();
+        storage.socd.push(SocdPair {
+            first: Action::Left,
+            second: Action::Right,
+            mode,
+        });
+        storage
+    }
+
+    fn press(storage: &mut StateStorage<Action>, action: Action, now: Timestamp) {
+        storage.resolve_socd(&action, &StateAction::Activated, now);
+    }
+
+    fn release(
+        storage: &mut StateStorage<Action>,
+        action: Action,
+        now: Timestamp,
+    ) -> Vec<(Action, StateAction)> {
+        storage
+            .resolve_socd(&action, &StateAction::Deactivated, now)
+            .into_iter()
+            .map(|(side, state_action, _)| (side, state_action))
+            .collect()
+    }
+
+    fn active(storage: &StateStorage<Action>) -> Vec<Action> {
+        vec![Action::Left, Action::Right]
+            .into_iter()
+            .filter(|side| storage.is_active(side))
+            .collect()
+    }
+
+    #[test]
+    fn threshold_presses_once_crossed() {
+        let threshold = threshold(0.6, 0.4);
+        assert_eq!(threshold_edge(&threshold, 0.5, false), None);
+        assert_eq!(threshold_edge(&threshold, 0.6, false), Some(RawState::Press));
+        assert_eq!(threshold_edge(&threshold, 0.9, true), None);
+    }
+
+    #[test]
+    fn threshold_releases_below_release_value() {
+        let threshold = threshold(0.6, 0.4);
+        assert_eq!(threshold_edge(&threshold, 0.5, true), None);
+        assert_eq!(threshold_edge(&threshold, 0.4, true), Some(RawState::Release));
+        assert_eq!(threshold_edge(&threshold, 0.1, false), None);
+    }
+
+    #[test]
+    fn inverted_threshold_presses_below() {
+        let threshold = threshold(-0.5, -0.2);
+        assert_eq!(threshold_edge(&threshold, -0.3, false), None);
+        assert_eq!(threshold_edge(&threshold, -0.6, false), Some(RawState::Press));
+        assert_eq!(threshold_edge(&threshold, -0.3, true), None);
+        assert_eq!(threshold_edge(&threshold, 0.0, true), Some(RawState::Release));
+    }
+
+    #[test]
+    fn cooldown_blocks_until_elapsed() {
+        let mapping = Mapping::new(RawType::Key(KeyCode::Space), Action::Fire).with_cooldown(1.0);
+        let mut storage = StateStorage::new();
+        assert_eq!(limited(&mapping, fire(), &mut storage, 0.0), Some(ActionType::Action));
+        assert_eq!(limited(&mapping, fire(), &mut storage, 0.5), None);
+        assert_eq!(limited(&mapping, fire(), &mut storage, 1.0), Some(ActionType::Action));
+    }
+
+    #[test]
+    fn cooldown_reports_remaining_time() {
+        let mapping = Mapping::new(RawType::Key(KeyCode::Space), Action::Fire)
+            .with_cooldown(1.0)
+            .with_blocked_feedback();
+        let mut storage = StateStorage::new();
+        limited(&mapping, fire(), &mut storage, 0.0);
+        assert_eq!(
+            limited(&mapping, fire(), &mut storage, 0.25),
+            Some(ActionType::Blocked(0.75))
+        );
+    }
+
+    #[test]
+    fn debounce_drops_presses_after_recent_edge() {
+        let mapping = Mapping::new(RawType::Key(KeyCode::G), Action::Guard).with_debounce(0.05);
+        let mut storage = StateStorage::new();
+        let press = || state(Action::Guard, StateAction::Activated);
+        let release = || state(Action::Guard, StateAction::Deactivated);
+        assert!(limited(&mapping, press(), &mut storage, 0.0).is_some());
+        assert!(limited(&mapping, release(), &mut storage, 0.01).is_some());
+        assert_eq!(limited(&mapping, press(), &mut storage, 0.03), None);
+        assert!(limited(&mapping, press(), &mut storage, 0.1).is_some());
+    }
+
+    #[test]
+    fn max_rate_limits_triggers_per_window() {
+        let mapping =
+            Mapping::new(RawType::Key(KeyCode::Space), Action::Fire).with_max_rate(2, 1.0);
+        let mut storage = StateStorage::new();
+        assert!(limited(&mapping, fire(), &mut storage, 0.0).is_some());
+        assert!(limited(&mapping, fire(), &mut storage, 0.1).is_some());
+        assert_eq!(limited(&mapping, fire(), &mut storage, 0.2), None);
+        assert!(limited(&mapping, fire(), &mut storage, 1.05).is_some());
+    }
+
+    #[test]
+    fn releases_pass_only_for_accepted_presses() {
+        let mapping = Mapping::new(RawType::Key(KeyCode::G), Action::Guard)
+            .with_cooldown(1.0)
+            .with_debounce(0.5);
+        let mut storage = StateStorage::new();
+        let press = || state(Action::Guard, StateAction::Activated);
+        let release = || state(Action::Guard, StateAction::Deactivated);
+        assert!(limited(&mapping, press(), &mut storage, 0.0).is_some());
+        assert_eq!(
+            limited(&mapping, release(), &mut storage, 0.1),
+            Some(ActionType::State(StateAction::Deactivated, 0.0))
+        );
+        assert_eq!(limited(&mapping, press(), &mut storage, 0.7), None);
+        assert_eq!(limited(&mapping, release(), &mut storage, 0.8), None);
+    }
+
+    #[test]
+    fn active_states_are_not_limited() {
+        let mapping = Mapping::new(RawType::Key(KeyCode::G), Action::Guard).with_cooldown(1.0);
+        let mut storage = StateStorage::new();
+        let active = state(Action::Guard, StateAction::Active);
+        assert!(limited(&mapping, active, &mut storage, 0.0).is_some());
+    }
+
+    #[test]
+    fn socd_neutral_cancels_both_sides() {
+        let mut storage = socd(Socd::Neutral);
+        press(&mut storage, Action::Left, 0.0);
+        assert_eq!(active(&storage), vec![Action::Left]);
+        press(&mut storage, Action::Right, 0.1);
+        assert_eq!(active(&storage), vec![]);
+    }
+
+    #[test]
+    fn socd_last_input_wins() {
+        let mut storage = socd(Socd::LastInputWins);
+        press(&mut storage, Action::Left, 0.0);
+        press(&mut storage, Action::Right, 0.1);
+        assert_eq!(active(&storage), vec![Action::Right]);
+        press(&mut storage, Action::Left, 0.2);
+        assert_eq!(active(&storage), vec![Action::Right]);
+    }
+
+    #[test]
+    fn socd_first_input_wins() {
+        let mut storage = socd(Socd::FirstInputWins);
+        press(&mut storage, Action::Left, 0.0);
+        press(&mut storage, Action::Right, 0.1);
+        assert_eq!(active(&storage), vec![Action::Left]);
+    }
+
+    #[test]
+    fn socd_priority_side_wins() {
+        let mut storage = socd(Socd::Priority(Action::Right));
+        press(&mut storage, Action::Right, 0.0);
+        press(&mut storage, Action::Left, 0.1);
+        assert_eq!(active(&storage), vec![Action::Right]);
+    }
+
+    #[test]
+    fn socd_release_restores_held_side() {
+        let modes = vec![
+            (Socd::Neutral, Action::Right, Action::Left),
+            (Socd::LastInputWins, Action::Right, Action::Left),
+            (Socd::FirstInputWins, Action::Left, Action::Right),
+            (Socd::Priority(Action::Left), Action::Left, Action::Right),
+        ];
+        for (mode, released, held) in modes {
+            let mut storage = socd(mode);
+            press(&mut storage, Action::Left, 0.0);
+            press(&mut storage, Action::Right, 0.1);
+            let changes = release(&mut storage, released.clone(), 0.2);
+            assert!(changes.contains(&(held.clone(), StateAction::Activated)));
+            assert_eq!(active(&storage), vec![held]);
+            assert_eq!(release(&mut storage, released, 0.3), vec![]);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use event::RangeDiff;
+
+pub trait RangeProcessor: Send {
+    fn process(&self, value: RangeDiff) -> RangeDiff;
+}
+
+impl<F> RangeProcessor for F
+where
+    F: Fn(RangeDiff) -> RangeDiff + Send,
+{
+    fn process(&self, value: RangeDiff) -> RangeDiff {
+        self(value)
+    }
+}
+
+pub trait Predicate: Send {
+    fn check(&self, user: &dyn Any) -> bool;
+}
+
+impl<F> Predicate for F
+where
+    F: Fn(&dyn Any) -> bool + Send,
+{
+    fn check(&self, user: &dyn Any) -> bool {
+        self(user)
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    processors: HashMap<String, Box<dyn RangeProcessor>>,
+    predicates: HashMap<String, Box<dyn Predicate>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn with_processor<P>(&mut self, name: &str, processor: P) -> &mut Self
+    where
+        P: RangeProcessor + 'static,
+    {
+        self.processors
+            .insert(name.to_string(), Box::new(processor));
+        self
+    }
+
+    pub fn processor(&self, name: &str) -> Option<&dyn RangeProcessor> {
+        self.processors.get(name).map(|p| p.as_ref())
+    }
+
+    pub fn with_predicate<P>(&mut self, name: &str, predicate: P) -> &mut Self
+    where
+        P: Predicate + 'static,
+    {
+        self.predicates
+            .insert(name.to_string(), Box::new(predicate));
+        self
+    }
+
+    pub fn predicate(&self, name: &str) -> Option<&dyn Predicate> {
+        self.predicates.get(name).map(|p| p.as_ref())
+    }
+}
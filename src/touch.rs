@@ -0,0 +1,355 @@
+use winit;
+
+use event::RangeDiff;
+use types::{RawState, TouchGesture, VirtualControl, VirtualKind, WindowPosition};
+
+pub const LONG_PRESS_TIME: f64 = 0.5;
+pub const DRAG_THRESHOLD: f64 = 0.02;
+
+#[derive(Debug, Clone)]
+pub struct Gesture {
+    pub gesture: TouchGesture,
+    pub state: RawState,
+    pub position: WindowPosition,
+    pub delta: RangeDiff,
+}
+
+#[derive(Debug, Clone)]
+pub struct VirtualInput {
+    pub name: String,
+    pub state: RawState,
+    pub position: WindowPosition,
+    pub value: RangeDiff,
+}
+
+#[derive(Debug, Clone)]
+struct TouchPoint {
+    id: u64,
+    start: WindowPosition,
+    position: WindowPosition,
+    start_time: f64,
+    dragging: bool,
+    long_pressed: bool,
+    consumed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TouchData {
+    points: Vec<TouchPoint>,
+    multi: bool,
+    captured: Vec<(u64, VirtualControl)>,
+}
+
+impl TouchData {
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn positions(&self) -> Vec<(u64, WindowPosition)> {
+        self.points.iter().map(|p| (p.id, p.position)).collect()
+    }
+
+    pub fn update_virtual(
+        &mut self,
+        raw_input: &winit::Event,
+        size: (f64, f64),
+        controls: &[&VirtualControl],
+    ) -> Vec<VirtualInput> {
+        let touch = match *raw_input {
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::Touch(ref touch),
+                ..
+            } => touch,
+            _ => return Vec::default(),
+        };
+        let position = (touch.location.0 / size.0, touch.location.1 / size.1);
+        let index = self.captured.iter().position(|&(id, _)| id == touch.id);
+        match (touch.phase, index) {
+            (winit::TouchPhase::Started, None) => {
+                match controls.iter().find(|c| c.contains(position)) {
+                    Some(control) => {
+                        self.captured.push((touch.id, (*control).clone()));
+                        vec![virtual_input(control, RawState::Press, position)]
+                    }
+                    None => Vec::default(),
+                }
+            }
+            (winit::TouchPhase::Moved, Some(index)) => {
+                let control = &self.captured[index].1;
+                match control.kind {
+                    VirtualKind::Stick => vec![virtual_input(control, RawState::Press, position)],
+                    VirtualKind::Button => Vec::default(),
+                }
+            }
+            (winit::TouchPhase::Ended, Some(index)) |
+            (winit::TouchPhase::Cancelled, Some(index)) => {
+                let (_, control) = self.captured.remove(index);
+                let mut input = virtual_input(&control, RawState::Release, position);
+                input.value = (0.0, 0.0);
+                vec![input]
+            }
+            _ => Vec::default(),
+        }
+    }
+
+    pub fn release_all(&mut self) -> Vec<VirtualInput> {
+        self.points.clear();
+        self.multi = false;
+        self.captured
+            .drain(..)
+            .map(|(_, control)| virtual_input(&control, RawState::Release, control.center()))
+            .collect()
+    }
+
+    pub fn update(
+        &mut self,
+        raw_input: &winit::Event,
+        size: (f64, f64),
+        now: f64,
+    ) -> Vec<Gesture> {
+        match *raw_input {
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::Touch(ref touch),
+                ..
+            } if !self.captured.iter().any(|&(id, _)| id == touch.id) => {
+                let position = (touch.location.0 / size.0, touch.location.1 / size.1);
+                match touch.phase {
+                    winit::TouchPhase::Started => self.started(touch.id, position, now),
+                    winit::TouchPhase::Moved => self.moved(touch.id, position, now, size),
+                    winit::TouchPhase::Ended => self.ended(touch.id, now, false),
+                    winit::TouchPhase::Cancelled => self.ended(touch.id, now, true),
+                }
+            }
+            _ => Vec::default(),
+        }
+    }
+
+    pub fn update_time(&mut self, now: f64) -> Vec<Gesture> {
+        self.points
+            .iter_mut()
+            .filter(|p| !p.consumed && !p.dragging && !p.long_pressed)
+            .filter(|p| now - p.start_time >= LONG_PRESS_TIME)
+            .map(|p| {
+                p.long_pressed = true;
+                gesture(TouchGesture::LongPress, RawState::Press, p.position, (0.0, 0.0))
+            })
+            .collect()
+    }
+
+    fn started(&mut self, id: u64, position: WindowPosition, now: f64) -> Vec<Gesture> {
+        let mut gestures = Vec::default();
+        for p in self.points.iter_mut().filter(|p| !p.consumed) {
+            gestures.extend(release_single(p));
+            p.consumed = true;
+        }
+        let consumed = !self.points.is_empty();
+        self.points.push(TouchPoint {
+            id,
+            start: position,
+            position,
+            start_time: now,
+            dragging: false,
+            long_pressed: false,
+            consumed,
+        });
+        gestures
+    }
+
+    fn moved(
+        &mut self,
+        id: u64,
+        position: WindowPosition,
+        now: f64,
+        size: (f64, f64),
+    ) -> Vec<Gesture> {
+        let index = match self.points.iter().position(|p| p.id == id) {
+            Some(index) => index,
+            None => return Vec::default(),
+        };
+        if self.points.len() >= 2 && index < 2 {
+            let before = (self.points[0].position, self.points[1].position);
+            self.points[index].position = position;
+            let after = (self.points[0].position, self.points[1].position);
+            self.multi = true;
+            return multi_gestures(before, after, size);
+        }
+        let point = &mut self.points[index];
+        let previous = point.position;
+        point.position = position;
+        if point.consumed {
+            return Vec::default();
+        }
+        if point.dragging {
+            vec![
+                gesture(
+                    TouchGesture::Drag,
+                    RawState::Press,
+                    position,
+                    (position.0 - previous.0, position.1 - previous.1),
+                ),
+            ]
+        } else if distance(point.start, position) > DRAG_THRESHOLD {
+            let mut gestures = release_single(point);
+            point.dragging = true;
+            gestures.push(gesture(
+                TouchGesture::Drag,
+                RawState::Press,
+                position,
+                (position.0 - point.start.0, position.1 - point.start.1),
+            ));
+            gestures
+        } else if !point.long_pressed && now - point.start_time >= LONG_PRESS_TIME {
+            point.long_pressed = true;
+            vec![
+                gesture(TouchGesture::LongPress, RawState::Press, position, (0.0, 0.0)),
+            ]
+        } else {
+            Vec::default()
+        }
+    }
+
+    fn ended(&mut self, id: u64, now: f64, cancelled: bool) -> Vec<Gesture> {
+        let mut point = match self.points.iter().position(|p| p.id == id) {
+            Some(index) => self.points.remove(index),
+            None => return Vec::default(),
+        };
+        if point.consumed {
+            if self.multi && self.points.len() < 2 {
+                self.multi = false;
+                let centroid = self.points
+                    .first()
+                    .map(|p| midpoint(p.position, point.position))
+                    .unwrap_or(point.position);
+                return vec![TouchGesture::Pinch, TouchGesture::Pan, TouchGesture::Rotate]
+                    .into_iter()
+                    .map(|g| gesture(g, RawState::Release, centroid, (0.0, 0.0)))
+                    .collect();
+            }
+            return Vec::default();
+        }
+        if point.dragging || point.long_pressed || cancelled {
+            return release_single(&mut point);
+        }
+        let kind = if now - point.start_time >= LONG_PRESS_TIME {
+            TouchGesture::LongPress
+        } else {
+            TouchGesture::Tap
+        };
+        vec![
+            gesture(kind.clone(), RawState::Press, point.position, (0.0, 0.0)),
+            gesture(kind, RawState::Release, point.position, (0.0, 0.0)),
+        ]
+    }
+}
+
+fn virtual_input(
+    control: &VirtualControl,
+    state: RawState,
+    position: WindowPosition,
+) -> VirtualInput {
+    let value = match control.kind {
+        VirtualKind::Stick => {
+            let center = control.center();
+            (
+                clamp_unit((position.0 - center.0) / (control.region.2 / 2.0)),
+                clamp_unit((position.1 - center.1) / (control.region.3 / 2.0)),
+            )
+        }
+        VirtualKind::Button => (0.0, 0.0),
+    };
+    VirtualInput {
+        name: control.name.clone(),
+        state,
+        position,
+        value,
+    }
+}
+
+fn clamp_unit(value: f64) -> f64 {
+    value.max(-1.0).min(1.0)
+}
+
+fn release_single(point: &mut TouchPoint) -> Vec<Gesture> {
+    let kind = if point.dragging {
+        TouchGesture::Drag
+    } else if point.long_pressed {
+        TouchGesture::LongPress
+    } else {
+        return Vec::default();
+    };
+    point.dragging = false;
+    point.long_pressed = false;
+    vec![gesture(kind, RawState::Release, point.position, (0.0, 0.0))]
+}
+
+fn multi_gestures(
+    before: (WindowPosition, WindowPosition),
+    after: (WindowPosition, WindowPosition),
+    size: (f64, f64),
+) -> Vec<Gesture> {
+    let centroid_before = midpoint(before.0, before.1);
+    let centroid = midpoint(after.0, after.1);
+    vec![
+        gesture(
+            TouchGesture::Pinch,
+            RawState::Press,
+            centroid,
+            (distance(after.0, after.1) - distance(before.0, before.1), 0.0),
+        ),
+        gesture(
+            TouchGesture::Pan,
+            RawState::Press,
+            centroid,
+            (
+                centroid.0 - centroid_before.0,
+                centroid.1 - centroid_before.1,
+            ),
+        ),
+        gesture(
+            TouchGesture::Rotate,
+            RawState::Press,
+            centroid,
+            (
+                normalize_angle(angle(after.0, after.1, size) - angle(before.0, before.1, size)),
+                0.0,
+            ),
+        ),
+    ]
+}
+
+fn gesture(
+    gesture: TouchGesture,
+    state: RawState,
+    position: WindowPosition,
+    delta: RangeDiff,
+) -> Gesture {
+    Gesture {
+        gesture,
+        state,
+        position,
+        delta,
+    }
+}
+
+fn midpoint(a: WindowPosition, b: WindowPosition) -> WindowPosition {
+    ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+}
+
+fn distance(a: WindowPosition, b: WindowPosition) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn angle(a: WindowPosition, b: WindowPosition, size: (f64, f64)) -> f64 {
+    ((b.1 - a.1) * size.1).atan2((b.0 - a.0) * size.0)
+}
+
+fn normalize_angle(angle: f64) -> f64 {
+    use std::f64::consts::PI;
+    if angle > PI {
+        angle - 2.0 * PI
+    } else if angle < -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
@@ -2,6 +2,9 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use winit;
 
+use event::RangeDiff;
+use touch::TouchData;
+
 use std::clone::Clone;
 use std::cmp::Eq;
 use std::fmt::Debug;
@@ -171,19 +174,196 @@ pub enum MouseButton {
     Other(u8),
 }
 
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Misc1,
+    Paddle1,
+    Paddle2,
+    Paddle3,
+    Paddle4,
+    Touchpad,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub enum TouchGesture {
+    Tap,
+    LongPress,
+    Drag,
+    Pinch,
+    Pan,
+    Rotate,
+}
+
 #[derive(Debug, Clone)]
 pub enum DeviceType {
     Keyboard,
     Mouse,
+    Gamepad,
+    Touch,
     Window,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 pub enum RawType {
     Button(MouseButton),
     Key(KeyCode),
     Motion,
     Char,
+    Touch(TouchGesture),
+    Virtual(String),
+    Composite(Composite),
+    Axis(u32),
+    Scroll,
+    Pressure,
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis),
+}
+
+impl RawType {
+    pub fn device_type(&self) -> DeviceType {
+        match *self {
+            RawType::Key(_) | RawType::Char => DeviceType::Keyboard,
+            RawType::Button(_) | RawType::Motion | RawType::Scroll | RawType::Pressure => {
+                DeviceType::Mouse
+            }
+            RawType::Touch(_) | RawType::Virtual(_) => DeviceType::Touch,
+            RawType::Axis(_) | RawType::GamepadButton(_) | RawType::GamepadAxis(_) => {
+                DeviceType::Gamepad
+            }
+            RawType::Composite(ref composite) => composite
+                .components()
+                .first()
+                .map(|c| c.device_type())
+                .unwrap_or(DeviceType::Keyboard),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Threshold {
+    pub press: f64,
+    pub release: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repeat {
+    pub delay: f64,
+    pub interval: f64,
+    #[serde(default = "default_acceleration")] pub acceleration: f64,
+    #[serde(default)] pub min_interval: f64,
+}
+
+fn default_acceleration() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Limit {
+    #[serde(default)] pub cooldown: f64,
+    #[serde(default)] pub debounce: f64,
+    #[serde(default)] pub max_rate: Option<(u32, f64)>,
+    #[serde(default)] pub feedback: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Ramp {
+    pub rise: f64,
+    pub fall: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub enum Composite {
+    Axis {
+        negative: Box<RawType>,
+        positive: Box<RawType>,
+        #[serde(default)] ramp: Option<Ramp>,
+    },
+    Vector {
+        up: Box<RawType>,
+        down: Box<RawType>,
+        left: Box<RawType>,
+        right: Box<RawType>,
+        #[serde(default)] ramp: Option<Ramp>,
+    },
+}
+
+impl Composite {
+    pub fn axis(negative: RawType, positive: RawType) -> Self {
+        Composite::Axis {
+            negative: Box::new(negative),
+            positive: Box::new(positive),
+            ramp: None,
+        }
+    }
+
+    pub fn vector(up: RawType, down: RawType, left: RawType, right: RawType) -> Self {
+        Composite::Vector {
+            up: Box::new(up),
+            down: Box::new(down),
+            left: Box::new(left),
+            right: Box::new(right),
+            ramp: None,
+        }
+    }
+
+    pub fn with_ramp(mut self, rise: f64, fall: f64) -> Self {
+        match self {
+            Composite::Axis { ref mut ramp, .. } | Composite::Vector { ref mut ramp, .. } => {
+                *ramp = Some(Ramp { rise, fall })
+            }
+        }
+        self
+    }
+
+    pub fn ramp(&self) -> Option<&Ramp> {
+        match *self {
+            Composite::Axis { ref ramp, .. } | Composite::Vector { ref ramp, .. } => {
+                ramp.as_ref()
+            }
+        }
+    }
+
+    pub fn components(&self) -> Vec<&RawType> {
+        match *self {
+            Composite::Axis {
+                ref negative,
+                ref positive,
+                ..
+            } => vec![negative, positive],
+            Composite::Vector {
+                ref up,
+                ref down,
+                ref left,
+                ref right,
+                ..
+            } => vec![up, down, left, right],
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -208,20 +388,84 @@ pub enum MappedType {
     Range,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub enum Processor {
+    DeadZone(f64),
+    Scale(f64, f64),
+    InvertX,
+    InvertY,
+    Curve(f64),
+    Smooth(f64),
+    Custom(String),
+}
+
 pub trait ActionMetadata {
     fn mapped_type(&self) -> MappedType;
     fn args(&self) -> Vec<ActionArgument>;
+
+    fn groups(&self) -> Vec<String> {
+        Vec::default()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum Condition<ACTION, ID> {
+    State(ACTION),
+    Context(ID),
+    All(Vec<Condition<ACTION, ID>>),
+    Any(Vec<Condition<ACTION, ID>>),
+    Not(Box<Condition<ACTION, ID>>),
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum ContextSwitch<ID> {
+    Activate(ID, u32),
+    Deactivate(ID),
+    Toggle(ID),
+    Push(ID),
+    PushModal(ID),
+    Pop,
+    Momentary(ID),
+    OneShot(ID),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MappingHandle(pub u64);
+
+#[derive(Debug, PartialEq)]
+pub enum MappingError {
+    UnknownContext,
+    UnknownMapping,
+    MissingMappedType,
+    InvalidRawType,
+    InvalidOption(String),
 }
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct Mapping<ACTION: Clone> {
+pub struct Mapping<ACTION: Clone, ID: Clone> {
+    #[serde(skip)] pub handle: MappingHandle,
     pub raw_type: RawType,
     pub state: Option<RawState>,
-    pub state_active: Option<ACTION>,
     pub action: ACTION,
 
+    #[serde(default = "default_co")] pub condition: Option<Condition<ACTION, ID>>,
+
     #[serde(default = "default_mt")] pub mapped_type: Option<MappedType>,
     #[serde(default = "default_aa")] pub action_args: Vec<ActionArgument>,
+    #[serde(default = "default_pr")] pub processors: Vec<Processor>,
+    #[serde(default = "default_th")] pub threshold: Option<Threshold>,
+    #[serde(default = "default_ho")] pub hold: Option<f64>,
+    #[serde(default)] pub toggle: bool,
+    #[serde(default = "default_re")] pub repeat: Option<Repeat>,
+    #[serde(default)] pub suppress_repeat: bool,
+    #[serde(default = "default_li")] pub limit: Option<Limit>,
+    #[serde(default = "default_sw")] pub switch: Option<ContextSwitch<ID>>,
+    #[serde(default)] pub emit_action: bool,
+}
+
+fn default_co<ACTION, ID>() -> Option<Condition<ACTION, ID>> {
+    None
 }
 
 fn default_mt() -> Option<MappedType> {
@@ -232,15 +476,49 @@ fn default_aa() -> Vec<ActionArgument> {
     Vec::default()
 }
 
-impl<ACTION: ActionMetadata + Clone> Mapping<ACTION> {
+fn default_pr() -> Vec<Processor> {
+    Vec::default()
+}
+
+fn default_th() -> Option<Threshold> {
+    None
+}
+
+fn default_ho() -> Option<f64> {
+    None
+}
+
+fn default_re() -> Option<Repeat> {
+    None
+}
+
+fn default_li() -> Option<Limit> {
+    None
+}
+
+fn default_sw<ID>() -> Option<ContextSwitch<ID>> {
+    None
+}
+
+impl<ACTION: ActionMetadata + Clone, ID: Clone> Mapping<ACTION, ID> {
     pub fn new(raw_type: RawType, action: ACTION) -> Self {
         Mapping {
+            handle: MappingHandle::default(),
             raw_type: raw_type,
             mapped_type: Some(action.mapped_type()),
             action_args: action.args(),
             action: action,
             state: None,
-            state_active: None,
+            condition: None,
+            processors: Vec::default(),
+            threshold: None,
+            hold: None,
+            toggle: false,
+            repeat: None,
+            suppress_repeat: false,
+            limit: None,
+            switch: None,
+            emit_action: false,
         }
     }
 
@@ -249,13 +527,82 @@ impl<ACTION: ActionMetadata + Clone> Mapping<ACTION> {
         self
     }
 
-    pub fn with_state_active(mut self, state: ACTION) -> Self {
-        self.state_active = Some(state);
+    pub fn with_state_active(self, state: ACTION) -> Self {
+        self.with_condition(Condition::State(state))
+    }
+
+    pub fn with_condition(mut self, condition: Condition<ACTION, ID>) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    pub fn with_processor(mut self, processor: Processor) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    pub fn with_threshold(mut self, press: f64, release: f64) -> Self {
+        self.threshold = Some(Threshold { press, release });
+        self
+    }
+
+    pub fn with_hold(mut self, duration: f64) -> Self {
+        self.hold = Some(duration);
+        self
+    }
+
+    pub fn with_toggle(mut self) -> Self {
+        self.toggle = true;
+        self
+    }
+
+    pub fn with_repeat(mut self, delay: f64, interval: f64, acceleration: f64) -> Self {
+        self.repeat = Some(Repeat {
+            delay,
+            interval,
+            acceleration,
+            min_interval: 0.0,
+        });
+        self
+    }
+
+    pub fn with_suppress_repeat(mut self) -> Self {
+        self.suppress_repeat = true;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: f64) -> Self {
+        self.limit.get_or_insert_with(Limit::default).cooldown = cooldown;
+        self
+    }
+
+    pub fn with_debounce(mut self, debounce: f64) -> Self {
+        self.limit.get_or_insert_with(Limit::default).debounce = debounce;
+        self
+    }
+
+    pub fn with_max_rate(mut self, count: u32, window: f64) -> Self {
+        self.limit.get_or_insert_with(Limit::default).max_rate = Some((count, window));
+        self
+    }
+
+    pub fn with_blocked_feedback(mut self) -> Self {
+        self.limit.get_or_insert_with(Limit::default).feedback = true;
+        self
+    }
+
+    pub fn with_switch(mut self, switch: ContextSwitch<ID>) -> Self {
+        self.switch = Some(switch);
+        self
+    }
+
+    pub fn with_emit_action(mut self) -> Self {
+        self.emit_action = true;
         self
     }
 }
 
-impl<ACTION: Clone> Mapping<ACTION> {
+impl<ACTION: Clone, ID: Clone> Mapping<ACTION, ID> {
     pub fn sanitize(&mut self) {
         match self.mapped_type {
             Some(MappedType::Action) => if self.state == None {
@@ -266,9 +613,108 @@ impl<ACTION: Clone> Mapping<ACTION> {
             },
             _ => (),
         }
+        if self.hold.is_some() || self.repeat.is_some() {
+            self.state = Some(RawState::Press);
+        }
+        if let Some(ContextSwitch::Momentary(_)) = self.switch {
+            self.state = None;
+        }
     }
+
+    pub fn validate(&self) -> Result<(), MappingError> {
+        let (action, state, range) = match self.mapped_type {
+            Some(MappedType::Action) => (true, false, false),
+            Some(MappedType::State) => (false, true, false),
+            Some(MappedType::Range) => (false, false, true),
+            None => return Err(MappingError::MissingMappedType),
+        };
+        let analog = match self.raw_type {
+            RawType::GamepadAxis(_) if !range && self.threshold.is_none() => {
+                return Err(MappingError::InvalidRawType)
+            }
+            RawType::Axis(_) |
+            RawType::GamepadAxis(_) |
+            RawType::Motion |
+            RawType::Scroll |
+            RawType::Pressure => true,
+            RawType::Composite(_) if !range => return Err(MappingError::InvalidRawType),
+            _ => false,
+        };
+        let invalid = |option: &str| Err(MappingError::InvalidOption(option.to_string()));
+        if self.threshold.is_some() && !analog {
+            return invalid("threshold");
+        }
+        if self.hold.is_some() && (!action || self.repeat.is_some()) {
+            return invalid("hold");
+        }
+        if self.repeat.is_some() && !action {
+            return invalid("repeat");
+        }
+        if self.toggle && !state {
+            return invalid("toggle");
+        }
+        if !self.processors.is_empty() && !range {
+            return invalid("processors");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum Socd<ACTION> {
+    Neutral,
+    LastInputWins,
+    FirstInputWins,
+    Priority(ACTION),
+}
+
+#[derive(Debug, Clone)]
+pub struct SocdPair<ACTION> {
+    pub first: ACTION,
+    pub second: ACTION,
+    pub mode: Socd<ACTION>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum VirtualKind {
+    Button,
+    Stick,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualControl {
+    pub name: String,
+    pub kind: VirtualKind,
+    pub region: (f64, f64, f64, f64),
 }
 
+impl VirtualControl {
+    pub fn button(name: &str, region: (f64, f64, f64, f64)) -> Self {
+        VirtualControl {
+            name: name.to_string(),
+            kind: VirtualKind::Button,
+            region,
+        }
+    }
+
+    pub fn stick(name: &str, region: (f64, f64, f64, f64)) -> Self {
+        VirtualControl {
+            name: name.to_string(),
+            kind: VirtualKind::Stick,
+            region,
+        }
+    }
+
+    pub fn contains(&self, position: WindowPosition) -> bool {
+        let (x, y, w, h) = self.region;
+        position.0 >= x && position.0 <= x + w && position.1 >= y && position.1 <= y + h
+    }
+
+    pub fn center(&self) -> WindowPosition {
+        let (x, y, w, h) = self.region;
+        (x + w / 2.0, y + h / 2.0)
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Context<ACTION, ID>
@@ -277,7 +723,22 @@ where
     ID: Clone,
 {
     pub id: ID,
-    pub mappings: Vec<Mapping<ACTION>>,
+    pub mappings: Vec<Mapping<ACTION, ID>>,
+    #[serde(default = "default_vc")] pub virtual_controls: Vec<VirtualControl>,
+    #[serde(default = "default_pa")] pub parents: Vec<ID>,
+    #[serde(default = "default_rm")] pub removed: Vec<ACTION>,
+}
+
+fn default_vc() -> Vec<VirtualControl> {
+    Vec::default()
+}
+
+fn default_pa<ID>() -> Vec<ID> {
+    Vec::default()
+}
+
+fn default_rm<ACTION>() -> Vec<ACTION> {
+    Vec::default()
 }
 
 impl<ACTION, ID> Context<ACTION, ID>
@@ -289,20 +750,63 @@ where
         Self::new_with_mappings(id, Vec::default())
     }
 
-    pub fn new_with_mappings(id: ID, mappings: Vec<Mapping<ACTION>>) -> Self {
-        Context { id, mappings }
+    pub fn new_with_mappings(id: ID, mappings: Vec<Mapping<ACTION, ID>>) -> Self {
+        Context {
+            id,
+            mappings,
+            virtual_controls: Vec::default(),
+            parents: Vec::default(),
+            removed: Vec::default(),
+        }
     }
 
-    pub fn with_mapping(mut self, mapping: Mapping<ACTION>) -> Self {
+    pub fn with_mapping(mut self, mapping: Mapping<ACTION, ID>) -> Self {
         self.mappings.push(mapping);
         self
     }
 
-    pub fn with_mappings(mut self, mut mappings: Vec<Mapping<ACTION>>) -> Self {
+    pub fn with_mappings(mut self, mut mappings: Vec<Mapping<ACTION, ID>>) -> Self {
         self.mappings.append(&mut mappings);
         self
     }
 
+    pub fn with_virtual_control(mut self, control: VirtualControl) -> Self {
+        self.virtual_controls.push(control);
+        self
+    }
+
+    pub fn with_parent(mut self, parent: ID) -> Self {
+        self.parents.push(parent);
+        self
+    }
+
+    pub fn without_action(mut self, action: ACTION) -> Self {
+        self.removed.push(action);
+        self
+    }
+
+    pub fn inherit(&mut self, parent: &Context<ACTION, ID>) {
+        let (removed, own) = (&self.removed, &self.mappings);
+        // The child's own mappings override the parent's for the same action or input.
+        let inherited: Vec<Mapping<ACTION, ID>> = parent
+            .mappings
+            .iter()
+            .filter(|m| !removed.contains(&m.action))
+            .filter(|m| {
+                !own.iter().any(|o| {
+                    o.action == m.action || (o.raw_type == m.raw_type && o.state == m.state)
+                })
+            })
+            .cloned()
+            .collect();
+        self.mappings.extend(inherited);
+        for control in parent.virtual_controls.iter() {
+            if !self.virtual_controls.iter().any(|c| c.name == control.name) {
+                self.virtual_controls.push(control.clone());
+            }
+        }
+    }
+
     pub fn sanitize(&mut self) {
         self.mappings.iter_mut().for_each(|m| m.sanitize());
     }
@@ -324,6 +828,14 @@ where
 {
     pub priority: u32,
     pub context_id: ID,
+    pub kind: ContextKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextKind {
+    Normal,
+    Modal,
+    Global,
 }
 
 pub type WindowPosition = (f64, f64);
@@ -334,9 +846,14 @@ where
     ID: Debug + Clone,
 {
     pub fn new(priority: u32, context_id: &ID) -> ActiveContext<ID> {
+        Self::with_kind(priority, context_id, ContextKind::Normal)
+    }
+
+    pub fn with_kind(priority: u32, context_id: &ID, kind: ContextKind) -> ActiveContext<ID> {
         ActiveContext {
             priority,
             context_id: context_id.clone(),
+            kind,
         }
     }
 }
@@ -375,10 +892,67 @@ pub struct StateInfo {
     pub stop_time: f64,
 }
 
+#[derive(Debug, Clone)]
+pub struct PendingHold<ACTION> {
+    pub action: ACTION,
+    pub raw_type: RawType,
+    pub start_time: f64,
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingRepeat<ACTION> {
+    pub action: ACTION,
+    pub raw_type: RawType,
+    pub repeat: Repeat,
+    pub next_time: f64,
+    pub interval: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitInfo {
+    pub last_trigger: f64,
+    pub last_edge: f64,
+    pub recent: Vec<f64>,
+}
+
+impl Default for LimitInfo {
+    fn default() -> Self {
+        LimitInfo {
+            last_trigger: f64::NEG_INFINITY,
+            last_edge: f64::NEG_INFINITY,
+            recent: Vec::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimedContext<ID> {
+    pub context_id: ID,
+    pub deadline: f64,
+    pub complete_on_action: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeInfo {
+    pub value: RangeDiff,
+    pub target: RangeDiff,
+    pub time: f64,
+}
+
 #[derive(Debug, Clone)]
 pub struct WindowData {
     pub size: (f64, f64),
     pub cursor_position: Option<WindowPosition>,
+    pub touches: TouchData,
+    pub held_keys: Vec<KeyCode>,
+    pub held_buttons: Vec<MouseButton>,
+    pub repeated: bool,
+    pub time: f64,
+    pub motion: Option<RangeDiff>,
+    pub scroll: Option<RangeDiff>,
+    pub last_update: f64,
+    pub update_interval: f64,
 }
 
 #[derive(Debug)]
@@ -386,7 +960,19 @@ pub struct StateStorage<ACTION>
 where
     ACTION: Hash + Eq + Clone,
 {
-    pub states: HashMap<ACTION, StateInfo>,
+    pub(crate) states: HashMap<ACTION, StateInfo>,
+    pub(crate) ranges: HashMap<ACTION, RangeInfo>,
+    pub(crate) smoothed: HashMap<ACTION, RangeDiff>,
+    pub(crate) latched: HashMap<MappingHandle, ACTION>,
+    pub(crate) holds: Vec<PendingHold<ACTION>>,
+    pub(crate) toggles: HashMap<ACTION, bool>,
+    pub(crate) repeats: Vec<PendingRepeat<ACTION>>,
+    pub(crate) limits: HashMap<ACTION, Limit>,
+    pub(crate) limit_info: HashMap<ACTION, LimitInfo>,
+    pub(crate) socd: Vec<SocdPair<ACTION>>,
+    pub(crate) pressed: HashMap<ACTION, u64>,
+    pub(crate) press_sequence: u64,
+    pub(crate) masked: Vec<ACTION>,
 }
 
 impl<ACTION> StateStorage<ACTION>
@@ -396,6 +982,18 @@ where
     pub fn new() -> StateStorage<ACTION> {
         StateStorage {
             states: HashMap::default(),
+            ranges: HashMap::default(),
+            smoothed: HashMap::default(),
+            latched: HashMap::default(),
+            holds: Vec::default(),
+            toggles: HashMap::default(),
+            repeats: Vec::default(),
+            limits: HashMap::default(),
+            limit_info: HashMap::default(),
+            socd: Vec::default(),
+            pressed: HashMap::default(),
+            press_sequence: 0,
+            masked: Vec::default(),
         }
     }
 
@@ -412,6 +1010,16 @@ where
             None => false,
         }
     }
+
+    pub fn states<'a>(&'a self) -> impl Iterator<Item = (ACTION, StateInfo)> + 'a {
+        self.states
+            .iter()
+            .map(|(action, info)| (action.clone(), info.clone()))
+    }
+
+    pub fn range(&self, action: &ACTION) -> Option<RangeDiff> {
+        self.ranges.get(action).map(|info| info.value)
+    }
 }
 
 
@@ -13,6 +13,7 @@ use std::str;
 use event::{ActionType, Argument, Event, StateAction};
 use types::*;
 
+#[deprecated(note = "use InputRebinder::frame instead")]
 pub struct StateTracker<ACTION>
 where
     ACTION: Hash + Eq + Clone,
@@ -20,6 +21,7 @@ where
     states: HashMap<ACTION, bool>,
 }
 
+#[allow(deprecated)]
 impl<ACTION> StateTracker<ACTION>
 where
     ACTION: Hash + Eq + Clone + Debug,
@@ -35,7 +37,7 @@ where
         ID: Hash + Eq + Clone + Debug,
     {
         for e in events {
-            if let Event::Controller(ref action, ActionType::State(ref state_action, _), _) = *e {
+            if let Event::Controller(ref action, ActionType::State(ref state_action, _), ..) = *e {
                 self.states
                     .insert(action.clone(), action_as_bool(state_action));
             }
@@ -79,7 +81,7 @@ impl<ACTION: Hash + Eq + Clone + Debug> TextHandler<ACTION> {
         ID: Hash + Eq + Clone + Debug,
     {
         for e in events {
-            if let Event::Controller(ref action, _, ref args) = *e {
+            if let Event::Controller(ref action, _, ref args, _) = *e {
                 if *action == self.text_action {
                     for arg in args {
                         if let Argument::Value(char) = *arg {